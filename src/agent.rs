@@ -0,0 +1,170 @@
+use crate::askpass::Askpass;
+use crate::keys::Reader;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const REQUEST_IDENTITIES: u8 = 11;
+const IDENTITIES_ANSWER: u8 = 12;
+// An agent that doesn't answer is as good as none
+const AGENT_TIMEOUT: Duration = Duration::from_secs(1);
+// Larger answers aren't sent by any agent
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// A key loaded in ssh-agent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    /// The public key in the SSH wire format.
+    pub blob: Vec<u8>,
+    pub comment: String,
+}
+
+/// The socket of the running ssh-agent.
+pub fn agent_socket() -> Option<PathBuf> {
+    std::env::var_os("SSH_AUTH_SOCK")
+        .filter(|socket| !socket.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Asks the agent listening on `socket` for the keys it holds.
+pub fn list_identities(socket: &Path) -> io::Result<Vec<Identity>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(AGENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(AGENT_TIMEOUT))?;
+    stream.write_all(&1u32.to_be_bytes())?;
+    stream.write_all(&[REQUEST_IDENTITIES])?;
+
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "agent answer too long",
+        ));
+    }
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message)?;
+
+    let mut reader = Reader::new(&message);
+    if reader.u8()? != IDENTITIES_ANSWER {
+        return Err(io::Error::other("the agent refused to list its keys"));
+    }
+    let count = reader.u32()?;
+    let mut identities = Vec::new();
+    for _ in 0..count {
+        identities.push(Identity {
+            blob: reader.string()?.to_vec(),
+            comment: String::from_utf8_lossy(reader.string()?).to_string(),
+        });
+    }
+    Ok(identities)
+}
+
+/// Whether `value` is a lifetime `ssh-add -t` accepts: seconds, or numbers followed by `s`,
+/// `m`, `h`, `d` or `w` such as `1h30m`.
+pub fn is_lifetime(value: &str) -> bool {
+    let mut digits = 0;
+    for c in value.chars() {
+        match c {
+            '0'..='9' => digits += 1,
+            's' | 'S' | 'm' | 'M' | 'h' | 'H' | 'd' | 'D' | 'w' | 'W' if digits > 0 => digits = 0,
+            _ => return false,
+        }
+    }
+    !value.is_empty()
+}
+
+/// Loads the key at `path` into the agent with `ssh-add`, answering its passphrase prompt with
+/// `passphrase`. The agent forgets it after `lifetime` if given.
+pub fn add_key(path: &Path, passphrase: &str, lifetime: Option<&str>) -> io::Result<()> {
+    let mut command = Command::new("ssh-add");
+    if let Some(lifetime) = lifetime {
+        command.arg("-t").arg(lifetime);
+    }
+    command
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let askpass = Askpass::serve(passphrase, "passphrase")?;
+    askpass.configure(&mut command, false)?;
+    let output = command.output()?;
+    drop(askpass);
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = stderr
+        .lines()
+        .rfind(|line| !line.trim().is_empty())
+        .unwrap_or("ssh-add failed");
+    Err(io::Error::other(error.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::{is_lifetime, list_identities, Identity};
+    use crate::test_util::temp_dir;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    fn string(data: &[u8]) -> Vec<u8> {
+        let mut string = (data.len() as u32).to_be_bytes().to_vec();
+        string.extend(data);
+        string
+    }
+
+    #[test]
+    fn test_list_identities() {
+        let socket = temp_dir("agent").join("socket");
+        let listener = UnixListener::bind(&socket).unwrap();
+        // Answers like ssh-agent holding two keys
+        let agent = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 5];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, [0, 0, 0, 1, 11]);
+            let mut answer = vec![12, 0, 0, 0, 2];
+            answer.extend(string(b"key one"));
+            answer.extend(string(b"me@laptop"));
+            answer.extend(string(b"key two"));
+            answer.extend(string(b""));
+            stream
+                .write_all(&(answer.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(&answer).unwrap();
+        });
+
+        let identities = list_identities(&socket).unwrap();
+        agent.join().unwrap();
+        assert_eq!(
+            identities,
+            vec![
+                Identity {
+                    blob: b"key one".to_vec(),
+                    comment: "me@laptop".to_string(),
+                },
+                Identity {
+                    blob: b"key two".to_vec(),
+                    comment: String::new(),
+                },
+            ]
+        );
+        let _ = std::fs::remove_file(&socket);
+    }
+
+    #[test]
+    fn test_is_lifetime() {
+        assert!(is_lifetime("3600"));
+        assert!(is_lifetime("1h30m"));
+        assert!(is_lifetime("2d"));
+        assert!(!is_lifetime(""));
+        assert!(!is_lifetime("h"));
+        assert!(!is_lifetime("1 hour"));
+        assert!(!is_lifetime("1hm"));
+    }
+}
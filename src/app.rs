@@ -1,29 +1,1625 @@
-use crate::server::ServerList;
-use std::time::Instant;
+use crate::agent::{agent_socket, is_lifetime, list_identities, Identity};
+use crate::backup::backup_dir;
+use crate::config_file::{ConfigEditor, HostSettings};
+use crate::deploy::{spawn_deploy, Deployment};
+use crate::events::AppEvent;
+use crate::history::{FileChange, History, SecretChange};
+use crate::keys::{
+    display_path, expand_home, fingerprint, public_key_blob, public_key_line, scan, KeyInventory,
+    KeyJob, KeyResult, KeyTask, KEY_TYPES,
+};
+use crate::known_hosts::{
+    display_type, host_name, known_hosts_paths, pin_keys, pinned_names, remove_entries,
+    user_known_hosts_paths, HostKey, HostKeyCheck, KnownHosts, KnownStatus, UnmatchedHosts,
+};
+use crate::popup::{
+    validate_group, AddKey, Confirm, ConfirmAction, HostForm, Input, InputAction, NewKey, Popup,
+    VaultAction,
+};
+use crate::probe::{ProbeResult, ProbeStatus, Prober, Report, Target};
+use crate::resolver::{spawn_resolver, ResolvedHost};
+use crate::server::{group_path, parent_group, ServerItem, ServerList, OTHER_GROUP};
+use crate::tunnel::TunnelManager;
+use crate::vault::{vault_path, Vault};
+use ratatui::layout::Rect;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
+
+// Hosts are checked in parallel so one that doesn't answer doesn't hold up the others
+const PROBE_WORKERS: usize = 8;
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+// Host keys rarely change, they are fetched again less often than hosts are probed
+const HOST_KEY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Whether the password of `server` can only come from the vault.
+fn needs_vault(server: &ServerItem) -> bool {
+    server.password.is_none() && server.password_from.is_none()
+}
+
+/// A question to ask once a background job about a host is done.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pending {
+    /// Which keys to remove, once the addresses of the host are known.
+    ForgetHostKeys(String),
+    /// Whether to pin the keys the host presents, once they are fetched.
+    PinHostKeys(String),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub enum View {
+    #[default]
+    Servers,
+    Tunnels,
+    Keys,
+    KnownHosts,
+}
 
 #[derive(Debug)]
 pub struct App {
     pub should_exit: bool,
     pub has_selected: bool,
+    pub view: View,
     pub search_query: String,
     pub is_searching: bool,
+    pub show_diagnostics: bool,
     pub server_list: ServerList,
     pub last_click_time: Option<Instant>,
+    pub resolver: Option<Receiver<ResolvedHost>>,
+    pub tunnels: TunnelManager,
+    /// Shown in the footer until the next key press.
+    pub message: Option<String>,
+    pub popup: Option<Popup>,
+    /// Hosts cut to be moved elsewhere by `paste`.
+    pub clipboard: Vec<ServerItem>,
+    /// The visible row a mouse drag started on.
+    pub drag_from: Option<usize>,
+    /// Where the rows of the server list were last drawn, to find the row under the mouse.
+    pub list_area: Rect,
+    pub history: History,
+    /// Where config files are backed up before being written.
+    pub backup_dir: Option<PathBuf>,
+    /// The host passwords, once unlocked.
+    pub vault: Option<Vault>,
+    pub vault_path: Option<PathBuf>,
+    /// Whether unlocking the vault was skipped, it isn't asked again for the session.
+    pub vault_skipped: bool,
+    /// The keys loaded in ssh-agent, `None` if no agent could be reached.
+    pub agent_keys: Option<Vec<Identity>>,
+    /// Whether the agent holds the key of each `IdentityFile`, for the keys that could be read.
+    pub loaded_keys: HashMap<String, bool>,
+    pub keys: KeyInventory,
+    /// Where background work reports to, once the run loop started.
+    pub events: Option<Sender<AppEvent>>,
+    pub prober: Option<Prober>,
+    /// The last status of each host checked, and when it was received.
+    pub probes: HashMap<String, (ProbeStatus, Instant)>,
+    /// The hosts being checked.
+    pub probing: HashSet<String>,
+    pub known_hosts: KnownHosts,
+    /// The keys each host presented to ssh-keyscan, empty if it couldn't be reached, and when.
+    pub host_keys: HashMap<String, (Vec<HostKey>, Instant)>,
+    /// The hosts whose keys are being fetched.
+    pub scanning: HashSet<String>,
+    /// The IP addresses each host resolved to.
+    pub addresses: HashMap<String, Vec<IpAddr>>,
+    /// The hosts being looked up.
+    pub resolving: HashSet<String>,
+    pub pending: Option<Pending>,
+    pub unmatched_hosts: UnmatchedHosts,
 }
 
 impl App {
     pub fn new() -> color_eyre::Result<Self> {
-        Ok(Self {
+        let mut app = Self {
             should_exit: false,
             has_selected: false,
+            view: View::Servers,
             search_query: String::new(),
             is_searching: false,
+            show_diagnostics: false,
             server_list: ServerList::from_ssh_config(),
             last_click_time: None,
-        })
+            resolver: None,
+            tunnels: TunnelManager::default(),
+            message: None,
+            popup: None,
+            clipboard: Vec::new(),
+            drag_from: None,
+            list_area: Rect::default(),
+            history: History::default(),
+            backup_dir: backup_dir(),
+            vault: None,
+            vault_path: vault_path(),
+            vault_skipped: false,
+            agent_keys: None,
+            loaded_keys: HashMap::new(),
+            keys: KeyInventory::default(),
+            events: None,
+            prober: None,
+            probes: HashMap::new(),
+            probing: HashSet::new(),
+            known_hosts: KnownHosts::load(&known_hosts_paths()),
+            host_keys: HashMap::new(),
+            scanning: HashSet::new(),
+            addresses: HashMap::new(),
+            resolving: HashSet::new(),
+            pending: None,
+            unmatched_hosts: UnmatchedHosts::default(),
+        };
+        app.refresh_agent();
+        Ok(app)
+    }
+
+    /// Starts computing the effective configuration of every host with `ssh -G` in the background.
+    pub fn start_resolver(&mut self) {
+        let hosts = self.server_list.ssh_aliases();
+        self.resolver = Some(spawn_resolver(&self.server_list.config_path, hosts));
+    }
+
+    /// Applies the hosts resolved so far without blocking.
+    pub fn poll_resolver(&mut self) {
+        let mut resolved_any = false;
+        if let Some(resolver) = &self.resolver {
+            while let Ok(resolved) = resolver.try_recv() {
+                self.server_list.apply_resolved(resolved);
+                resolved_any = true;
+            }
+        }
+        // Resolving brings in the identity files ssh uses by default
+        if resolved_any {
+            self.refresh_agent();
+        }
+    }
+
+    /// Starts checking whether the hosts can be reached, the results of this and other
+    /// background work come back through `events`.
+    pub fn start_workers(&mut self, events: Sender<AppEvent>) {
+        self.prober = Some(Prober::new(PROBE_WORKERS, events.clone()));
+        self.events = Some(events);
+        self.probe_visible();
+    }
+
+    /// Checks the visible hosts that weren't checked in the last `PROBE_INTERVAL`.
+    pub fn probe_visible(&mut self) {
+        let Some(prober) = &self.prober else {
+            return;
+        };
+        for target in self
+            .server_list
+            .visible_items()
+            .into_iter()
+            .filter_map(Target::of)
+        {
+            let fresh = self
+                .probes
+                .get(&target.host)
+                .is_some_and(|(_, checked)| checked.elapsed() < PROBE_INTERVAL);
+            if fresh || self.probing.contains(&target.host) {
+                continue;
+            }
+            self.probing.insert(target.host.clone());
+            prober.probe(target);
+        }
+    }
+
+    pub fn apply_probe(&mut self, result: ProbeResult) {
+        match result.report {
+            Report::Status(status) => {
+                self.probing.remove(&result.host);
+                if let ProbeStatus::Up {
+                    version: Some(_), ..
+                } = status
+                {
+                    self.scan_host_keys(&result.host);
+                }
+                self.probes.insert(result.host, (status, Instant::now()));
+            }
+            Report::HostKeys(keys) => {
+                self.scanning.remove(&result.host);
+                let keys = keys.unwrap_or_default();
+                self.host_keys
+                    .insert(result.host.clone(), (keys.clone(), Instant::now()));
+                if self.pending == Some(Pending::PinHostKeys(result.host.clone())) {
+                    self.pending = None;
+                    if let Some(server) = self.find_host(&result.host) {
+                        self.offer_pin(&server, keys);
+                    }
+                }
+            }
+            Report::Addresses(addresses) => {
+                self.resolving.remove(&result.host);
+                self.addresses.insert(result.host.clone(), addresses);
+                if self.view == View::KnownHosts {
+                    self.refresh_unmatched_hosts();
+                }
+                if self.pending == Some(Pending::ForgetHostKeys(result.host.clone())) {
+                    self.pending = None;
+                    if let Some(server) = self.find_host(&result.host) {
+                        self.ask_forget(&server);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Looks up the addresses of `server` in the background, returning whether it is being looked
+    /// up.
+    fn resolve_host(&mut self, server: &ServerItem) -> bool {
+        if self.resolving.contains(&server.host) {
+            return true;
+        }
+        let Some(prober) = &self.prober else {
+            return false;
+        };
+        self.resolving.insert(server.host.clone());
+        prober.resolve(Target {
+            host: server.host.clone(),
+            address: server.hostname().to_string(),
+            port: u16::try_from(server.port).unwrap_or(22),
+        });
+        true
+    }
+
+    fn find_host(&self, host: &str) -> Option<ServerItem> {
+        self.server_list
+            .items
+            .iter()
+            .find(|item| !item.is_group && item.host == host)
+            .cloned()
+    }
+
+    /// Fetches the keys of the SSH server `host` in the background, unless that was done in the
+    /// last `HOST_KEY_INTERVAL`. Returns whether they are being fetched.
+    fn scan_host_keys(&mut self, host: &str) -> bool {
+        let fresh = self
+            .host_keys
+            .get(host)
+            .is_some_and(|(_, scanned)| scanned.elapsed() < HOST_KEY_INTERVAL);
+        if self.scanning.contains(host) {
+            return true;
+        }
+        if fresh {
+            return false;
+        }
+        let target = self.find_host(host).as_ref().and_then(Target::of);
+        let (Some(prober), Some(target)) = (&self.prober, target) else {
+            return false;
+        };
+        self.scanning.insert(target.host.clone());
+        prober.scan_keys(target);
+        true
+    }
+
+    /// The last known status of `server`, `None` until it was checked.
+    pub fn probe_status(&self, server: &ServerItem) -> Option<&ProbeStatus> {
+        self.probes.get(&server.host).map(|(status, _)| status)
     }
 
     pub fn update_search(&mut self) {
         self.server_list.filter_items(&self.search_query);
     }
+
+    /// Starts a tunnel with the forwards of the selected host, or stops it if it is running.
+    pub fn toggle_tunnel(&mut self) {
+        let Some(server) = self.server_list.selected() else {
+            return;
+        };
+        if server.is_group {
+            return;
+        }
+
+        if let Some(i) = self.tunnels.position(&server.host) {
+            self.message = Some(format!("Tunnel to {} stopped", server.host));
+            self.tunnels.stop(i);
+            return;
+        }
+        if server.forwards.is_empty() {
+            self.message = Some(format!("{} has no forwards", server.host));
+            return;
+        }
+        if needs_vault(server) && self.ask_unlock(VaultAction::StartTunnel) {
+            return;
+        }
+        let Some(server) = self
+            .server_list
+            .selected()
+            .map(|server| self.with_secret(server))
+        else {
+            return;
+        };
+        match self.tunnels.start(&server, &self.server_list.config_path) {
+            Ok(()) => self.message = Some(format!("Tunnel to {} started", server.host)),
+            Err(e) => self.message = Some(format!("Failed to start tunnel: {}", e)),
+        }
+    }
+
+    /// Restarts tunnels whose ssh process exited and refreshes their status.
+    pub fn poll_tunnels(&mut self) {
+        let messages = self.tunnels.poll();
+        if !messages.is_empty() {
+            self.message = Some(messages.join(", "));
+        }
+    }
+
+    pub fn stop_selected_tunnel(&mut self) {
+        if let Some(tunnel) = self.tunnels.selected().and_then(|i| self.tunnels.stop(i)) {
+            self.message = Some(format!("Tunnel to {} stopped", tunnel.server.host));
+        }
+    }
+
+    pub fn restart_selected_tunnel(&mut self) {
+        let Some(tunnel) = self
+            .tunnels
+            .selected()
+            .map(|i| &mut self.tunnels.tunnels[i])
+        else {
+            return;
+        };
+        self.message = Some(match tunnel.restart() {
+            Ok(()) => format!("Tunnel to {} restarted", tunnel.server.host),
+            Err(e) => format!("Failed to restart tunnel: {}", e),
+        });
+    }
+
+    /// Quits to log in to the selected host, or expands or collapses the selected group.
+    pub fn login_selected(&mut self) {
+        let Some(server) = self.server_list.selected().cloned() else {
+            return;
+        };
+        if server.is_group {
+            self.server_list.toggle_group();
+            return;
+        }
+        if needs_vault(&server) && self.ask_unlock(VaultAction::Login) {
+            return;
+        }
+        if let Some(warning) = self.check_host_key(&server) {
+            self.popup = Some(Popup::Confirm(Confirm {
+                message: warning,
+                action: ConfirmAction::HostKeyChanged(Box::new(server)),
+            }));
+            return;
+        }
+        self.offer_key_or_login(&server);
+    }
+
+    /// Logs in to `server`, offering to add its key to the agent first if it isn't loaded.
+    fn offer_key_or_login(&mut self, server: &ServerItem) {
+        // Without its key in the agent, the login would fail or fall back to a password prompt
+        let uses_password = self.with_secret(server).password.is_some();
+        if let (Some(key), false) = (self.unloaded_key(server), uses_password) {
+            self.popup = Some(Popup::Confirm(Confirm {
+                message: format!("{} isn't loaded in ssh-agent, add it first?", key),
+                action: ConfirmAction::AddKey(AddKey {
+                    key,
+                    lifetime: None,
+                    login: Some(server.host.clone()),
+                }),
+            }));
+            return;
+        }
+        self.quit_to_login();
+    }
+
+    /// What known_hosts says about the key of `server`, `None` for groups.
+    pub fn known_status(&self, server: &ServerItem) -> Option<KnownStatus> {
+        let port = u16::try_from(server.port).ok()?;
+        if server.is_group {
+            return None;
+        }
+        Some(self.known_hosts.status(&host_name(server.hostname(), port)))
+    }
+
+    /// How the keys `server` last presented compare to known_hosts, `None` until they were
+    /// fetched. Hosts behind a proxy aren't checked.
+    pub fn host_key_check(&self, server: &ServerItem) -> Option<HostKeyCheck> {
+        let target = Target::of(server)?;
+        let (live, _) = self.host_keys.get(&server.host)?;
+        if live.is_empty() {
+            return None;
+        }
+        Some(
+            self.known_hosts
+                .check(&host_name(&target.address, target.port), live),
+        )
+    }
+
+    /// A warning if ssh would refuse the keys `server` last presented.
+    fn check_host_key(&self, server: &ServerItem) -> Option<String> {
+        match self.host_key_check(server)? {
+            HostKeyCheck::Mismatch {
+                key_type,
+                known,
+                live,
+                path,
+                line,
+            } => Some(format!(
+                "The {} key of {} changed from {} ({}:{}) to {}, ssh will refuse it. Connect anyway?",
+                key_type,
+                server.host,
+                known,
+                path.display(),
+                line,
+                live
+            )),
+            HostKeyCheck::Revoked { fingerprint } => Some(format!(
+                "{} presents the revoked key {}. Connect anyway?",
+                server.host, fingerprint
+            )),
+            HostKeyCheck::Match | HostKeyCheck::Unknown | HostKeyCheck::CertAuthority => None,
+        }
+    }
+
+    /// Asks to remove the keys the user's known_hosts files pin for the selected host, under any
+    /// name ssh may have used for it.
+    pub fn forget_selected_host_keys(&mut self) {
+        let Some(server) = self
+            .server_list
+            .selected()
+            .filter(|item| !item.is_group)
+            .cloned()
+        else {
+            return;
+        };
+        if self.addresses.contains_key(&server.host) {
+            self.ask_forget(&server);
+            return;
+        }
+        // The keys may be pinned under its IP addresses, which are looked up first
+        if !self.resolve_host(&server) {
+            self.ask_forget(&server);
+            return;
+        }
+        self.message = Some(format!("Looking up {}...", server.host));
+        self.pending = Some(Pending::ForgetHostKeys(server.host));
+    }
+
+    /// Asks to remove the keys pinned for `server` under its names and known addresses.
+    fn ask_forget(&mut self, server: &ServerItem) {
+        if self.popup.is_some() {
+            return;
+        }
+        let addresses = self
+            .addresses
+            .get(&server.host)
+            .cloned()
+            .unwrap_or_default();
+        let names = pinned_names(server, &addresses);
+        let entries = self.known_hosts.pinned(&names, &user_known_hosts_paths());
+        if entries.is_empty() {
+            self.message = Some(format!("No key of {} in known_hosts", server.host));
+            return;
+        }
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|entry| format!("{}:{}", display_path(&entry.path), entry.line))
+            .collect();
+        self.message = None;
+        self.popup = Some(Popup::Confirm(Confirm {
+            message: format!(
+                "Remove the known_hosts keys of {} ({})? The file is backed up first.",
+                server.host,
+                lines.join(", ")
+            ),
+            action: ConfirmAction::ForgetHostKeys(Box::new(server.clone()), entries),
+        }));
+    }
+
+    /// Once the keys of `server` were removed, asks to pin the `keys` it presents now.
+    fn offer_pin(&mut self, server: &ServerItem, keys: Vec<HostKey>) {
+        let done = format!("Removed the known_hosts keys of {}", server.host);
+        if keys.is_empty() || self.popup.is_some() {
+            self.message = Some(done);
+            return;
+        }
+        let fingerprints: Vec<String> = keys
+            .iter()
+            .map(|(key_type, blob)| {
+                format!("{} {}", display_type(blob, key_type), fingerprint(blob))
+            })
+            .collect();
+        self.message = None;
+        self.popup = Some(Popup::Confirm(Confirm {
+            message: format!(
+                "{}. Pin the keys it presents now? {}",
+                done,
+                fingerprints.join(", ")
+            ),
+            action: ConfirmAction::PinHostKeys(Box::new(server.clone()), keys),
+        }));
+    }
+
+    fn pin_host_keys(&mut self, server: &ServerItem, keys: &[HostKey]) -> io::Result<()> {
+        let Some(target) = Target::of(server) else {
+            return Ok(());
+        };
+        let Some(path) = user_known_hosts_paths().into_iter().next() else {
+            return Err(io::Error::other("no home directory"));
+        };
+        let name = host_name(&target.address, target.port);
+        let change = pin_keys(&path, &name, keys, self.backup_dir.as_deref())?;
+        self.record(
+            format!("pin the host keys of {}", server.host),
+            vec![change],
+        );
+        Ok(())
+    }
+
+    /// Lists the known_hosts keys no host is looked up by, looking up the addresses of the hosts
+    /// first since keys may be pinned under them.
+    pub fn show_known_hosts(&mut self) {
+        let servers: Vec<ServerItem> = self
+            .server_list
+            .items
+            .iter()
+            .filter(|item| !item.is_group && !self.addresses.contains_key(&item.host))
+            .cloned()
+            .collect();
+        for server in &servers {
+            self.resolve_host(server);
+        }
+        self.refresh_unmatched_hosts();
+        self.view = View::KnownHosts;
+    }
+
+    fn refresh_unmatched_hosts(&mut self) {
+        self.unmatched_hosts
+            .refresh(&self.known_hosts, &self.server_list.items, &self.addresses);
+    }
+
+    pub fn remove_selected_known_host(&mut self) {
+        // Until then, keys pinned under the address of a host would look unmatched
+        if !self.resolving.is_empty() {
+            self.message = Some("Wait until the hosts are looked up".to_string());
+            return;
+        }
+        let Some(entry) = self.unmatched_hosts.selected().cloned() else {
+            return;
+        };
+        self.popup = Some(Popup::Confirm(Confirm {
+            message: format!(
+                "Remove line {} of {}? The file is backed up first.",
+                entry.line,
+                display_path(&entry.path)
+            ),
+            action: ConfirmAction::RemoveKnownHost(Box::new(entry)),
+        }));
+    }
+
+    fn quit_to_login(&mut self) {
+        self.should_exit = true;
+        self.has_selected = true;
+    }
+
+    /// Asks the agent for its keys again and checks which keys of the config it holds.
+    pub fn refresh_agent(&mut self) {
+        self.agent_keys = agent_socket().and_then(|socket| list_identities(&socket).ok());
+        self.loaded_keys.clear();
+        let Some(agent_keys) = &self.agent_keys else {
+            return;
+        };
+        for server in &self.server_list.items {
+            for identity_file in &server.identity_files {
+                if self.loaded_keys.contains_key(identity_file) {
+                    continue;
+                }
+                if let Ok(blob) = public_key_blob(&expand_home(identity_file)) {
+                    let loaded = agent_keys.iter().any(|key| key.blob == blob);
+                    self.loaded_keys.insert(identity_file.clone(), loaded);
+                }
+            }
+        }
+    }
+
+    /// Whether the agent holds a key of `server`, `None` when that can't be told.
+    pub fn key_loaded(&self, server: &ServerItem) -> Option<bool> {
+        let loaded: Vec<bool> = server
+            .identity_files
+            .iter()
+            .filter_map(|identity_file| self.loaded_keys.get(identity_file).copied())
+            .collect();
+        // ssh tries every key, one of them is enough
+        (!loaded.is_empty()).then(|| loaded.contains(&true))
+    }
+
+    /// The first key of `server` to add to the agent, if it holds none of them.
+    fn unloaded_key(&self, server: &ServerItem) -> Option<String> {
+        if self.key_loaded(server) != Some(false) {
+            return None;
+        }
+        server
+            .identity_files
+            .iter()
+            .find(|identity_file| self.loaded_keys.get(*identity_file) == Some(&false))
+            .cloned()
+    }
+
+    /// Offers to load the key of the selected host into the agent.
+    pub fn add_selected_key(&mut self) {
+        let Some(server) = self.server_list.selected().filter(|item| !item.is_group) else {
+            return;
+        };
+        if self.agent_keys.is_none() {
+            self.message = Some("No ssh-agent reachable through SSH_AUTH_SOCK".to_string());
+            return;
+        }
+        match self.unloaded_key(server) {
+            Some(key) => self.ask_lifetime(AddKey {
+                key,
+                lifetime: None,
+                login: None,
+            }),
+            None if self.key_loaded(server) == Some(true) => {
+                self.message = Some(format!("The key of {} is already loaded", server.host))
+            }
+            None => self.message = Some(format!("{} has no readable key", server.host)),
+        }
+    }
+
+    /// Offers to load the key selected in the keys view into the agent.
+    pub fn add_listed_key(&mut self) {
+        let Some(key) = self.keys.selected().filter(|key| key.has_private) else {
+            return;
+        };
+        if self.agent_keys.is_none() {
+            self.message = Some("No ssh-agent reachable through SSH_AUTH_SOCK".to_string());
+            return;
+        }
+        let add_key = AddKey {
+            key: key.display_path(),
+            lifetime: None,
+            login: None,
+        };
+        self.ask_lifetime(add_key);
+    }
+
+    /// Whether the agent holds `blob`, `None` if no agent could be reached.
+    pub fn agent_holds(&self, blob: &[u8]) -> Option<bool> {
+        self.agent_keys
+            .as_ref()
+            .map(|agent_keys| agent_keys.iter().any(|key| key.blob == blob))
+    }
+
+    /// Scans `~/.ssh` and shows the keys found.
+    pub fn show_keys(&mut self) {
+        self.keys.refresh(&self.server_list.items);
+        self.view = View::Keys;
+    }
+
+    /// Asks for the type, path and passphrase of a key pair to generate.
+    pub fn new_key(&mut self) {
+        self.popup = Some(Popup::Input(Input {
+            title: format!(" New key type ({}) ", KEY_TYPES.join(", ")),
+            value: KEY_TYPES[0].to_string(),
+            action: InputAction::NewKeyType,
+            error: None,
+        }));
+    }
+
+    /// Takes the type and path of the key to generate, then its passphrase to run `ssh-keygen`
+    /// with.
+    fn submit_new_key_input(&mut self) {
+        let Some(Popup::Input(input)) = &mut self.popup else {
+            return;
+        };
+        match &input.action {
+            InputAction::NewKeyType => {
+                let key_type = input.value.trim().to_lowercase();
+                if !KEY_TYPES.contains(&key_type.as_str()) {
+                    input.error = Some(format!("Type must be one of {}", KEY_TYPES.join(", ")));
+                    return;
+                }
+                *input = Input {
+                    title: " New key path ".to_string(),
+                    value: format!("~/.ssh/id_{}", key_type),
+                    action: InputAction::NewKeyPath(NewKey {
+                        key_type,
+                        path: String::new(),
+                    }),
+                    error: None,
+                };
+            }
+            InputAction::NewKeyPath(new_key) => {
+                let path = input.value.trim();
+                if path.is_empty() {
+                    input.error = Some("Path is required".to_string());
+                    return;
+                }
+                if expand_home(path).exists() {
+                    input.error = Some(format!("{} already exists", path));
+                    return;
+                }
+                let new_key = NewKey {
+                    path: path.to_string(),
+                    ..new_key.clone()
+                };
+                *input = Input {
+                    title: format!(" Passphrase of {} ", new_key.path),
+                    value: String::new(),
+                    action: InputAction::NewKeyPassphrase(new_key),
+                    error: None,
+                };
+            }
+            InputAction::NewKeyPassphrase(new_key) => {
+                self.message = Some(format!("Generating {}...", new_key.path));
+                let job = KeyJob {
+                    task: KeyTask::Generate(new_key.clone()),
+                    passphrase: Zeroizing::new(std::mem::take(&mut input.value)),
+                };
+                self.run_key_job(job);
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks which key to install on the selected host, offering the first key pair of `~/.ssh`.
+    pub fn deploy_key_selected(&mut self) {
+        let Some(server) = self.server_list.selected().filter(|item| !item.is_group) else {
+            return;
+        };
+        // The password to install the key with may be in the vault
+        if needs_vault(server) && self.ask_unlock(VaultAction::DeployKey) {
+            return;
+        }
+        let Some(server) = self.server_list.selected().cloned() else {
+            return;
+        };
+        let dir = dirs::home_dir().unwrap_or_default().join(".ssh");
+        let key = scan(&dir, &[])
+            .into_iter()
+            .find(|key| key.has_private && key.has_public)
+            .map(|key| key.display_path())
+            .unwrap_or_default();
+        self.popup = Some(Popup::Input(Input {
+            title: format!(" Key to install on {} ", server.host),
+            value: key,
+            action: InputAction::DeployKey(Box::new(server)),
+            error: None,
+        }));
+    }
+
+    /// Installs the public key of the input popup on its host in the background.
+    fn submit_deploy_key(&mut self) {
+        let Some(Popup::Input(input)) = &mut self.popup else {
+            return;
+        };
+        let InputAction::DeployKey(server) = &input.action else {
+            return;
+        };
+        let identity_file = input.value.trim().to_string();
+        let path = expand_home(&identity_file);
+        if !path.is_file() {
+            input.error = Some(format!("{} doesn't exist", identity_file));
+            return;
+        }
+        let public_key = match public_key_line(&path) {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                input.error = Some(e.to_string());
+                return;
+            }
+        };
+        let Some(events) = self.events.clone() else {
+            input.error = Some("Background work isn't running".to_string());
+            return;
+        };
+        let server = (**server).clone();
+        let server = self.with_secret(&server);
+        self.popup = None;
+        self.message = Some(format!(
+            "Installing {} on {}...",
+            identity_file, server.host
+        ));
+        spawn_deploy(
+            server,
+            self.server_list.config_path.clone(),
+            identity_file,
+            public_key,
+            events,
+        );
+    }
+
+    /// Points the block of the host a key was installed on at the key instead of a password.
+    pub fn apply_deployment(&mut self, deployment: Deployment) {
+        let Deployment {
+            server,
+            identity_file,
+            result,
+        } = deployment;
+        if let Err(e) = result {
+            self.message = Some(format!(
+                "Cannot install {} on {}: {}",
+                identity_file, server.host, e
+            ));
+            return;
+        }
+        let description = format!("use {} for {}", identity_file, server.host);
+        let result = self
+            .edit_config(|editor, _| editor.use_identity_file(&server, &identity_file))
+            .and_then(|changes| {
+                let secrets = self.save_secret(Some(&server.host), "", "");
+                self.record_with_secrets(description, changes, secrets)
+            });
+        self.server_list.select_host(&server.host);
+        self.message = Some(match result {
+            Ok(()) => format!(
+                "Installed {} on {}, which logs in with it now",
+                identity_file, server.host
+            ),
+            Err(e) => format!(
+                "Installed {} on {} but cannot update the config: {}",
+                identity_file, server.host, e
+            ),
+        });
+    }
+
+    fn ask_lifetime(&mut self, add_key: AddKey) {
+        self.popup = Some(Popup::Input(Input {
+            title: format!(" Add {} to ssh-agent for ", add_key.key),
+            value: String::new(),
+            action: InputAction::KeyLifetime(add_key),
+            error: None,
+        }));
+    }
+
+    /// Takes the lifetime of the key to add, then its passphrase to run `ssh-add` with.
+    fn submit_key_input(&mut self) {
+        let Some(Popup::Input(input)) = &mut self.popup else {
+            return;
+        };
+        match &input.action {
+            InputAction::KeyLifetime(add_key) => {
+                let lifetime = input.value.trim();
+                if !lifetime.is_empty() && !is_lifetime(lifetime) {
+                    input.error = Some("Lifetime must look like 3600, 30m or 1h30m".to_string());
+                    return;
+                }
+                let add_key = AddKey {
+                    lifetime: Some(lifetime.to_string()).filter(|lifetime| !lifetime.is_empty()),
+                    ..add_key.clone()
+                };
+                *input = Input {
+                    title: format!(" Passphrase of {} ", add_key.key),
+                    value: String::new(),
+                    action: InputAction::KeyPassphrase(add_key),
+                    error: None,
+                };
+            }
+            InputAction::KeyPassphrase(add_key) => {
+                self.message = Some(format!("Adding {} to ssh-agent...", add_key.key));
+                let job = KeyJob {
+                    task: KeyTask::Add(add_key.clone()),
+                    passphrase: Zeroizing::new(std::mem::take(&mut input.value)),
+                };
+                self.run_key_job(job);
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs `ssh-keygen` or `ssh-add` on the workers, `apply_key_result` takes the outcome.
+    fn run_key_job(&mut self, job: KeyJob) {
+        self.popup = None;
+        match &self.prober {
+            Some(prober) => prober.run_key_job(job),
+            None => self.apply_key_result(job.run()),
+        }
+    }
+
+    /// Shows the key generated or added in the background, or asks the passphrase again with
+    /// the error.
+    pub fn apply_key_result(&mut self, result: KeyResult) {
+        let KeyResult { task, result } = result;
+        match (task, result) {
+            (KeyTask::Generate(new_key), Ok(())) => {
+                self.keys.refresh(&self.server_list.items);
+                self.keys.select(&expand_home(&new_key.path));
+                self.message = Some(format!("Generated {}", new_key.path));
+            }
+            (KeyTask::Add(add_key), Ok(())) => {
+                self.refresh_agent();
+                self.message = Some(format!("Added {} to ssh-agent", add_key.key));
+                // The selection may have moved in the meantime
+                if let Some(host) = &add_key.login {
+                    self.server_list.select_host(host);
+                    if self
+                        .server_list
+                        .selected()
+                        .is_some_and(|item| item.host == *host)
+                    {
+                        self.quit_to_login();
+                    }
+                }
+            }
+            // Something else may have been opened in the meantime
+            (task, Err(e)) if self.popup.is_some() => {
+                self.message = Some(match task {
+                    KeyTask::Generate(new_key) => {
+                        format!("Cannot generate {}: {}", new_key.path, e)
+                    }
+                    KeyTask::Add(add_key) => {
+                        format!("Cannot add {} to ssh-agent: {}", add_key.key, e)
+                    }
+                });
+            }
+            (KeyTask::Generate(new_key), Err(e)) => {
+                self.message = None;
+                self.popup = Some(Popup::Input(Input {
+                    title: format!(" Passphrase of {} ", new_key.path),
+                    value: String::new(),
+                    action: InputAction::NewKeyPassphrase(new_key),
+                    error: Some(e.to_string()),
+                }));
+            }
+            (KeyTask::Add(add_key), Err(e)) => {
+                self.message = None;
+                self.popup = Some(Popup::Input(Input {
+                    title: format!(" Passphrase of {} ", add_key.key),
+                    value: String::new(),
+                    action: InputAction::KeyPassphrase(add_key),
+                    error: Some(e.to_string()),
+                }));
+            }
+        }
+    }
+
+    /// `server` with the password the vault holds for it, unless its block has one.
+    pub fn with_secret(&self, server: &ServerItem) -> ServerItem {
+        let mut server = server.clone();
+        if server.password.is_none() {
+            server.password = self
+                .vault
+                .as_ref()
+                .and_then(|vault| vault.get(&server.host))
+                .map(String::from);
+        }
+        server
+    }
+
+    fn vault_locked(&self) -> bool {
+        self.vault.is_none()
+            && !self.vault_skipped
+            && self.vault_path.as_ref().is_some_and(|path| path.exists())
+    }
+
+    /// Asks for the vault passphrase before `action` if the vault is still locked, returning
+    /// whether it did.
+    fn ask_unlock(&mut self, action: VaultAction) -> bool {
+        if !self.vault_locked() {
+            return false;
+        }
+        self.popup = Some(Popup::Input(Input {
+            title: " Vault passphrase ".to_string(),
+            value: String::new(),
+            action: InputAction::UnlockVault(action),
+            error: None,
+        }));
+        true
+    }
+
+    /// Opens the vault with the passphrase of the input popup and carries on with the action
+    /// that needed it.
+    fn unlock_vault(&mut self) {
+        let Some(Popup::Input(input)) = &mut self.popup else {
+            return;
+        };
+        let (&InputAction::UnlockVault(action), Some(path)) = (&input.action, &self.vault_path)
+        else {
+            return;
+        };
+        let result = Vault::open(path, &input.value);
+        input.value.zeroize();
+        match result {
+            Ok(vault) => {
+                self.vault = Some(vault);
+                self.popup = None;
+                self.run_vault_action(action);
+            }
+            Err(e) => input.error = Some(e.to_string()),
+        }
+    }
+
+    /// Carries on without the vault for the rest of the session.
+    pub fn skip_vault(&mut self) {
+        if let Some(Popup::Input(Input {
+            action: InputAction::UnlockVault(action),
+            ..
+        })) = self.popup.take()
+        {
+            self.vault_skipped = true;
+            self.run_vault_action(action);
+        }
+    }
+
+    fn run_vault_action(&mut self, action: VaultAction) {
+        match action {
+            VaultAction::Login => self.login_selected(),
+            VaultAction::StartTunnel => self.toggle_tunnel(),
+            VaultAction::AddHost => self.add_host(),
+            VaultAction::EditHost => self.edit_selected_host(),
+            VaultAction::DeployKey => self.deploy_key_selected(),
+        }
+    }
+
+    /// Puts the password of the host saved as `host`, previously `old_host`, in the vault,
+    /// returning what changed so it can be undone along with the config. The vault is left as it
+    /// was if it cannot be saved.
+    fn save_secret(
+        &mut self,
+        old_host: Option<&str>,
+        host: &str,
+        secret: &str,
+    ) -> io::Result<Vec<SecretChange>> {
+        let Some(vault) = &mut self.vault else {
+            return Ok(Vec::new());
+        };
+        let mut changes = Vec::new();
+        if let Some(old_host) = old_host.filter(|&old_host| old_host != host) {
+            changes.push(SecretChange::new(vault, old_host, None));
+        }
+        if !secret.is_empty() || old_host == Some(host) {
+            let secret = Some(secret).filter(|secret| !secret.is_empty());
+            changes.push(SecretChange::new(vault, host, secret));
+        }
+        changes.retain(|change| change.before != change.after);
+        for change in &changes {
+            change.apply(vault, false);
+        }
+        if let Err(e) = vault.save() {
+            for change in &changes {
+                change.apply(vault, true);
+            }
+            return Err(e);
+        }
+        Ok(changes)
+    }
+
+    /// Opens the host form, in the group of the selected entry.
+    pub fn add_host(&mut self) {
+        if self.ask_unlock(VaultAction::AddHost) {
+            return;
+        }
+        let group = self
+            .server_list
+            .selected()
+            .map(|item| item.group.clone())
+            .filter(|group| group != OTHER_GROUP)
+            .unwrap_or_default();
+        let settings = HostSettings {
+            group,
+            ..HostSettings::default()
+        };
+        self.popup = Some(Popup::HostForm(Box::new(HostForm::new(None, settings))));
+    }
+
+    /// Opens the host form with the values of the selected host's own block.
+    pub fn edit_selected_host(&mut self) {
+        if self
+            .server_list
+            .selected()
+            .is_some_and(|item| !item.is_group)
+            && self.ask_unlock(VaultAction::EditHost)
+        {
+            return;
+        }
+        let Some(server) = self.server_list.selected().filter(|item| !item.is_group) else {
+            return;
+        };
+        match HostSettings::from_server(server) {
+            Ok(mut settings) => {
+                if settings.password.is_empty() {
+                    settings.password = self.with_secret(server).password.unwrap_or_default();
+                }
+                let form = HostForm::new(Some(server.clone()), settings);
+                self.popup = Some(Popup::HostForm(Box::new(form)));
+            }
+            Err(e) => self.message = Some(format!("Cannot edit {}: {}", server.host, e)),
+        }
+    }
+
+    /// Writes the host form to the config, keeping it open with the error if that fails.
+    pub fn save_host_form(&mut self) {
+        let Some(Popup::HostForm(form)) = &mut self.popup else {
+            return;
+        };
+        let mut settings = match form.validate(&self.server_list) {
+            Ok(settings) => settings,
+            Err(e) => {
+                form.error = Some(e);
+                return;
+            }
+        };
+        let editing = form.editing.clone();
+
+        // Once there is a vault, passwords go there instead of the config
+        let secret = if self.vault.is_some() {
+            Some(std::mem::take(&mut settings.password))
+        } else {
+            let unchanged = editing
+                .as_ref()
+                .and_then(|server| server.password.as_deref())
+                == Some(settings.password.as_str());
+            if self.vault_skipped && !settings.password.is_empty() && !unchanged {
+                form.error = Some("Unlock the vault to save passwords".to_string());
+                return;
+            }
+            None
+        };
+
+        let description = match &editing {
+            Some(server) => format!("edit {}", server.host),
+            None => format!("add {}", settings.host),
+        };
+        let result = self.edit_config(|editor, list| match &editing {
+            Some(server) => editor.update_host(list, server, &settings),
+            None => editor.add_host(list, &settings),
+        });
+        match result {
+            Ok(changes) => {
+                let secrets = match secret {
+                    Some(secret) => {
+                        let old_host = editing.as_ref().map(|server| server.host.as_str());
+                        self.save_secret(old_host, &settings.host, &secret)
+                    }
+                    None => Ok(Vec::new()),
+                };
+                self.popup = None;
+                self.message = Some(
+                    match self.record_with_secrets(description, changes, secrets) {
+                        Ok(()) => format!("Saved {}", settings.host),
+                        Err(e) => format!("Saved {} but not its password: {}", settings.host, e),
+                    },
+                );
+                self.server_list.select_host(&settings.host);
+            }
+            Err(e) => {
+                if let Some(Popup::HostForm(form)) = &mut self.popup {
+                    form.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Asks whether to delete the selected host or group.
+    pub fn delete_selected(&mut self) {
+        let Some(item) = self.server_list.selected() else {
+            return;
+        };
+        let confirm = if item.is_group {
+            Confirm {
+                message: format!(
+                    "Delete group {}? Its hosts move to {}",
+                    item.group,
+                    match parent_group(&item.group) {
+                        "" => OTHER_GROUP,
+                        parent => parent,
+                    }
+                ),
+                action: ConfirmAction::DeleteGroup(item.group.clone()),
+            }
+        } else {
+            Confirm {
+                message: format!("Delete {} from the config?", item.host),
+                action: ConfirmAction::DeleteHost(Box::new(item.clone())),
+            }
+        };
+        self.popup = Some(Popup::Confirm(confirm));
+    }
+
+    /// Runs the action of the confirmation popup.
+    pub fn confirm(&mut self) {
+        let Some(Popup::Confirm(confirm)) = self.popup.take() else {
+            return;
+        };
+        let (name, result) = match confirm.action {
+            ConfirmAction::AddKey(add_key) => {
+                self.ask_lifetime(add_key);
+                return;
+            }
+            ConfirmAction::HostKeyChanged(server) => {
+                self.offer_key_or_login(&server);
+                return;
+            }
+            ConfirmAction::ForgetHostKeys(server, entries) => {
+                match remove_entries(&entries, self.backup_dir.as_deref()) {
+                    Ok(changes) => {
+                        self.record(format!("forget the host keys of {}", server.host), changes);
+                        // The keys fetched before may be the ones of the server before it was
+                        // rebuilt
+                        self.host_keys.remove(&server.host);
+                        if self.scan_host_keys(&server.host) {
+                            self.message = Some(format!(
+                                "Removed the known_hosts keys of {}, fetching the ones it presents now...",
+                                server.host
+                            ));
+                            self.pending = Some(Pending::PinHostKeys(server.host.clone()));
+                        } else {
+                            self.offer_pin(&server, Vec::new());
+                        }
+                    }
+                    Err(e) => {
+                        self.message = Some(format!("Cannot remove keys of {}: {}", server.host, e))
+                    }
+                }
+                return;
+            }
+            ConfirmAction::PinHostKeys(server, keys) => {
+                self.message = Some(match self.pin_host_keys(&server, &keys) {
+                    Ok(()) => format!("Pinned the host keys of {}", server.host),
+                    Err(e) => format!("Cannot pin keys of {}: {}", server.host, e),
+                });
+                return;
+            }
+            ConfirmAction::RemoveKnownHost(entry) => {
+                let name = format!("line {} of {}", entry.line, display_path(&entry.path));
+                let result =
+                    remove_entries(std::slice::from_ref(&entry), self.backup_dir.as_deref())
+                        .map(|changes| self.record(format!("remove {}", name), changes));
+                (name, result)
+            }
+            ConfirmAction::DeleteHost(server) => (
+                server.host.clone(),
+                self.edit_config(|editor, _| editor.delete_host(&server))
+                    .and_then(|changes| {
+                        let secrets = self.save_secret(Some(&server.host), "", "");
+                        self.record_with_secrets(
+                            format!("delete {}", server.host),
+                            changes,
+                            secrets,
+                        )
+                    }),
+            ),
+            ConfirmAction::DeleteGroup(group) => (
+                group.clone(),
+                self.write_config(format!("delete group {}", group), |editor, list| {
+                    editor.delete_group(list, &group)
+                }),
+            ),
+        };
+        self.message = Some(match result {
+            Ok(()) => format!("Deleted {}", name),
+            Err(e) => format!("Cannot delete {}: {}", name, e),
+        });
+    }
+
+    /// Answers no to the confirmation popup, logging in without the key when asked to add it
+    /// first.
+    pub fn decline(&mut self) {
+        if let Some(Popup::Confirm(Confirm {
+            action: ConfirmAction::AddKey(add_key),
+            ..
+        })) = self.popup.take()
+        {
+            if add_key.login.is_some() {
+                self.quit_to_login();
+            }
+        }
+    }
+
+    pub fn add_group(&mut self) {
+        self.popup = Some(Popup::Input(Input {
+            title: " New group ".to_string(),
+            value: String::new(),
+            action: InputAction::AddGroup,
+            error: None,
+        }));
+    }
+
+    pub fn rename_selected_group(&mut self) {
+        let Some(item) = self.server_list.selected().filter(|item| item.is_group) else {
+            return;
+        };
+        self.popup = Some(Popup::Input(Input {
+            title: format!(" Rename {} ", item.group),
+            value: item.group.clone(),
+            action: InputAction::RenameGroup(item.group.clone()),
+            error: None,
+        }));
+    }
+
+    /// Runs the action of the input popup, keeping it open with the error if that fails.
+    pub fn submit_input(&mut self) {
+        let Some(Popup::Input(input)) = &mut self.popup else {
+            return;
+        };
+        match input.action {
+            InputAction::UnlockVault(_) => {
+                self.unlock_vault();
+                return;
+            }
+            InputAction::KeyLifetime(_) | InputAction::KeyPassphrase(_) => {
+                self.submit_key_input();
+                return;
+            }
+            InputAction::NewKeyType
+            | InputAction::NewKeyPath(_)
+            | InputAction::NewKeyPassphrase(_) => {
+                self.submit_new_key_input();
+                return;
+            }
+            InputAction::DeployKey(_) => {
+                self.submit_deploy_key();
+                return;
+            }
+            InputAction::AddGroup | InputAction::RenameGroup(_) => {}
+        }
+        let name = group_path(&input.value);
+        let renaming = match &input.action {
+            InputAction::RenameGroup(group) => Some(group.clone()),
+            _ => None,
+        };
+        if renaming.as_ref() == Some(&name) {
+            self.popup = None;
+            return;
+        }
+        if let Err(e) = validate_group(&name, &self.server_list) {
+            input.error = Some(e);
+            return;
+        }
+
+        let result = match renaming {
+            None => self.write_config(format!("add group {}", name), |editor, list| {
+                editor.add_group(list, &name)
+            }),
+            Some(group) => self.write_config(format!("rename group {}", group), |editor, list| {
+                editor.rename_group(list, &group, &name)
+            }),
+        };
+        match result {
+            Ok(()) => {
+                self.popup = None;
+                self.server_list.select_group(&name);
+            }
+            Err(e) => {
+                if let Some(Popup::Input(input)) = &mut self.popup {
+                    input.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Swaps the selected group with the one above it, or below it if `down`.
+    pub fn move_selected_group(&mut self, down: bool) {
+        let Some(group) = self
+            .server_list
+            .selected()
+            .filter(|item| item.is_group)
+            .map(|item| item.group.clone())
+        else {
+            return;
+        };
+        let mut moved = false;
+        let result = self.write_config(format!("move group {}", group), |editor, list| {
+            moved = editor.move_group(list, &group, down)?;
+            Ok(())
+        });
+        match result {
+            Ok(()) if !moved => {
+                self.message = Some(format!("{} cannot move further in its file", group))
+            }
+            Ok(()) => self.server_list.select_group(&group),
+            Err(e) => self.message = Some(format!("Cannot move {}: {}", group, e)),
+        }
+    }
+
+    /// Adds the selected host to the hosts to move with `paste`, or removes it from them.
+    pub fn toggle_cut(&mut self) {
+        let Some(server) = self.server_list.selected().filter(|item| !item.is_group) else {
+            return;
+        };
+        match self
+            .clipboard
+            .iter()
+            .position(|cut| cut.host == server.host)
+        {
+            Some(i) => {
+                self.clipboard.remove(i);
+            }
+            None => self.clipboard.push(server.clone()),
+        }
+    }
+
+    /// Moves the cut hosts after the selected host, or at the end of the selected group.
+    pub fn paste(&mut self) {
+        let Some(target) = self.server_list.selected().cloned() else {
+            return;
+        };
+        let servers = std::mem::take(&mut self.clipboard);
+        self.move_hosts(servers, &target);
+    }
+
+    /// Moves the host dragged from the visible row `from` onto the row `to`.
+    pub fn drop_host(&mut self, from: usize, to: usize) {
+        let visible_items = self.server_list.visible_items();
+        let (Some(server), Some(target)) = (visible_items.get(from), visible_items.get(to)) else {
+            return;
+        };
+        if server.is_group || from == to {
+            return;
+        }
+        let (server, target) = ((*server).clone(), (*target).clone());
+        self.move_hosts(vec![server], &target);
+    }
+
+    fn move_hosts(&mut self, servers: Vec<ServerItem>, target: &ServerItem) {
+        if servers.is_empty() {
+            return;
+        }
+        if servers.iter().any(|server| server.host == target.host) {
+            self.message = Some("Cannot move hosts next to themselves".to_string());
+            return;
+        }
+        let hosts: Vec<&str> = servers.iter().map(|server| server.host.as_str()).collect();
+        let hosts = hosts.join(", ");
+        let result = self.write_config(format!("move {}", hosts), |editor, list| {
+            editor.move_hosts(list, &servers, target)
+        });
+        match result {
+            Ok(()) => {
+                self.server_list.select_host(&servers[0].host);
+                let group = self.server_list.selected().map(|item| item.group.clone());
+                self.message = Some(format!("Moved {} to {}", hosts, group.unwrap_or_default()));
+            }
+            Err(e) => self.message = Some(format!("Cannot move hosts: {}", e)),
+        }
+    }
+
+    /// Reverts the last edit of the session.
+    pub fn undo(&mut self) {
+        let result = self
+            .history
+            .undo(self.backup_dir.as_deref(), self.vault.as_mut());
+        self.message = Some(match result {
+            Ok(Some(edit)) => format!("Undone: {}", edit.description),
+            Ok(None) => "Nothing to undo".to_string(),
+            Err(e) => format!("Cannot undo: {}", e),
+        });
+        self.reload();
+    }
+
+    /// Makes the last undone edit again.
+    pub fn redo(&mut self) {
+        let result = self
+            .history
+            .redo(self.backup_dir.as_deref(), self.vault.as_mut());
+        self.message = Some(match result {
+            Ok(Some(edit)) => format!("Redone: {}", edit.description),
+            Ok(None) => "Nothing to redo".to_string(),
+            Err(e) => format!("Cannot redo: {}", e),
+        });
+        self.reload();
+    }
+
+    /// Applies `edit` to the config files, backing them up first, and reloads the list if they
+    /// could be written. The edit can then be undone as `description`.
+    fn write_config(
+        &mut self,
+        description: String,
+        edit: impl FnOnce(&mut ConfigEditor, &ServerList) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let changes = self.edit_config(edit)?;
+        self.record(description, changes);
+        Ok(())
+    }
+
+    /// Applies `edit` to the config files, backing them up first, without recording it.
+    fn edit_config(
+        &mut self,
+        edit: impl FnOnce(&mut ConfigEditor, &ServerList) -> io::Result<()>,
+    ) -> io::Result<Vec<FileChange>> {
+        let mut editor = ConfigEditor::with_backups(self.backup_dir.clone());
+        edit(&mut editor, &self.server_list)?;
+        editor.save()
+    }
+
+    /// Keeps `changes` made by lazyssh so they can be undone, then reads the files again.
+    fn record(&mut self, description: String, changes: Vec<FileChange>) {
+        self.history.record(description, changes, Vec::new());
+        self.reload();
+    }
+
+    /// Like `record`, along with the vault passwords the same edit changed so undoing it restores
+    /// them too. Returns the error of `secrets`, the file changes being kept either way.
+    fn record_with_secrets(
+        &mut self,
+        description: String,
+        changes: Vec<FileChange>,
+        secrets: io::Result<Vec<SecretChange>>,
+    ) -> io::Result<()> {
+        let (secrets, result) = match secrets {
+            Ok(secrets) => (secrets, Ok(())),
+            Err(e) => (Vec::new(), Err(e)),
+        };
+        self.history.record(description, changes, secrets);
+        self.reload();
+        result
+    }
+
+    /// Reads the config again after it was written, resolving the hosts again if that was asked
+    /// for.
+    fn reload(&mut self) {
+        self.server_list.reload();
+        self.known_hosts = KnownHosts::load(&known_hosts_paths());
+        self.refresh_agent();
+        if self.view == View::Keys {
+            self.keys.refresh(&self.server_list.items);
+        }
+        if self.view == View::KnownHosts {
+            self.refresh_unmatched_hosts();
+        }
+        if self.resolver.is_some() {
+            self.start_resolver();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::app::App;
+    use crate::popup::Popup;
+    use crate::server::ServerList;
+    use crate::test_util::temp_dir;
+    use crate::vault::Vault;
+    use std::fs;
+
+    /// An app editing a config of its own, with an unlocked vault holding the password of `web`.
+    fn app(name: &str) -> App {
+        let dir = temp_dir(name);
+        let config = dir.join("config");
+        fs::write(&config, "Host web\n    HostName 10.0.0.1\n").unwrap();
+        let mut app = App::new().unwrap();
+        app.server_list = ServerList::from_config_file(&config);
+        app.backup_dir = None;
+        let mut vault = Vault::create_fast(&dir.join("vault"), "passphrase").unwrap();
+        vault.set("web", "s3cret");
+        vault.save().unwrap();
+        app.vault = Some(vault);
+        app
+    }
+
+    fn secret<'a>(app: &'a App, host: &str) -> Option<&'a str> {
+        app.vault.as_ref().unwrap().get(host)
+    }
+
+    #[test]
+    fn test_undo_delete_host() {
+        let mut app = app("app-delete");
+        app.server_list.select_host("web");
+        app.delete_selected();
+        app.confirm();
+        assert!(app.server_list.items.is_empty());
+        assert_eq!(secret(&app, "web"), None);
+
+        app.undo();
+        assert_eq!(app.server_list.items[0].host, "web");
+        assert_eq!(secret(&app, "web"), Some("s3cret"));
+        app.redo();
+        assert_eq!(secret(&app, "web"), None);
+    }
+
+    #[test]
+    fn test_undo_rename_host() {
+        let mut app = app("app-rename");
+        app.server_list.select_host("web");
+        app.edit_selected_host();
+        let Some(Popup::HostForm(form)) = &mut app.popup else {
+            panic!("no host form");
+        };
+        assert_eq!(form.values[6], "s3cret");
+        form.values[0] = "www".to_string();
+        app.save_host_form();
+        assert_eq!(app.server_list.items[0].host, "www");
+        assert_eq!(secret(&app, "web"), None);
+        assert_eq!(secret(&app, "www"), Some("s3cret"));
+
+        app.undo();
+        assert_eq!(app.server_list.items[0].host, "web");
+        assert_eq!(secret(&app, "web"), Some("s3cret"));
+        assert_eq!(secret(&app, "www"), None);
+    }
 }
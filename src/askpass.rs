@@ -0,0 +1,236 @@
+use crossterm::terminal;
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Set on ssh to the socket serving the password, its presence makes lazyssh run as askpass.
+pub const SOCKET_ENV: &str = "LAZYSSH_ASKPASS_SOCKET";
+/// Set on ssh to the word a prompt must contain to be answered with the secret.
+const PROMPT_ENV: &str = "LAZYSSH_ASKPASS_PROMPT";
+/// Set on ssh when other prompts (host keys, passphrases, ...) may be asked on the terminal.
+const INTERACTIVE_ENV: &str = "LAZYSSH_ASKPASS_INTERACTIVE";
+
+// Distinguishes the sockets of the tunnels and logins started by one lazyssh
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Hands a secret, a host's password or a key's passphrase, to the ssh or ssh-add process it is
+/// set on. They run lazyssh again as their `SSH_ASKPASS` program, which fetches the secret from
+/// a socket only the user can reach, so it never shows up in arguments or the environment. The
+/// secret is served once: being asked again means it was wrong. Stops serving when dropped.
+#[derive(Debug)]
+pub struct Askpass {
+    /// What the prompts the secret answers contain, such as `password`.
+    prompt: &'static str,
+    dir: PathBuf,
+    socket: PathBuf,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Askpass {
+    pub fn serve(secret: &str, prompt: &'static str) -> io::Result<Self> {
+        Self::serve_times(secret, prompt, 1)
+    }
+
+    /// Serves the secret `times` times, for programs asking it more than once such as ssh-keygen
+    /// confirming a new passphrase.
+    pub fn serve_times(secret: &str, prompt: &'static str, times: usize) -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "lazyssh-askpass-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        // Fails if the directory exists, so nobody can have prepared it
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        let socket = dir.join("socket");
+        let listener = match UnixListener::bind(&socket) {
+            Ok(listener) => listener,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(e);
+            }
+        };
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let secret = secret.to_string();
+        let thread = thread::spawn({
+            let stopped = stopped.clone();
+            move || {
+                let mut served = 0;
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if let (Ok(mut stream), true) = (stream, served < times) {
+                        if stream.write_all(secret.as_bytes()).is_ok() {
+                            served += 1;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            prompt,
+            dir,
+            socket,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Makes `command`, an ssh, ssh-add or ssh-keygen invocation, ask lazyssh for the secret. `interactive`
+    /// lets the prompts lazyssh can't answer go to the terminal, otherwise they fail.
+    pub fn configure(&self, command: &mut Command, interactive: bool) -> io::Result<()> {
+        command
+            .env("SSH_ASKPASS", std::env::current_exe()?)
+            .env("SSH_ASKPASS_REQUIRE", "force")
+            .env(SOCKET_ENV, &self.socket)
+            .env(PROMPT_ENV, self.prompt);
+        if interactive {
+            command.env(INTERACTIVE_ENV, "1");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Askpass {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Wakes the thread up from waiting for a connection
+        let _ = UnixStream::connect(&self.socket);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Runs lazyssh as ssh's askpass program: prints the answer to `prompt` and exits, failing when
+/// there is none.
+pub fn run(socket: OsString, prompt: Option<String>) -> ! {
+    let prompt = prompt.unwrap_or_default();
+    let word = std::env::var(PROMPT_ENV).unwrap_or_else(|_| "password".to_string());
+    let secret = if answers(&prompt, &word) {
+        fetch_secret(Path::new(&socket))
+    } else {
+        None
+    };
+    let answer = secret.or_else(|| {
+        let interactive = std::env::var_os(INTERACTIVE_ENV).is_some();
+        interactive
+            .then(|| read_tty(&prompt, prompt.contains("(yes/no")).ok())
+            .flatten()
+    });
+    match answer {
+        Some(answer) => {
+            println!("{}", answer);
+            std::process::exit(0);
+        }
+        None => std::process::exit(1),
+    }
+}
+
+/// Whether `prompt` asks for the secret, containing `word`. OpenSSH asks `user@host's password: `,
+/// keyboard-interactive servers mostly `Password: ` and ssh-add `Enter passphrase for ...: `.
+fn answers(prompt: &str, word: &str) -> bool {
+    prompt.to_lowercase().contains(word)
+}
+
+/// The secret served on `socket`, `None` once it was handed out.
+fn fetch_secret(socket: &Path) -> Option<String> {
+    let mut stream = UnixStream::connect(socket).ok()?;
+    let mut secret = String::new();
+    stream.read_to_string(&mut secret).ok()?;
+    Some(secret).filter(|secret| !secret.is_empty())
+}
+
+/// Asks `prompt` on the terminal, whatever stdin is, hiding the answer unless `echo`.
+pub fn read_tty(prompt: &str, echo: bool) -> io::Result<String> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    write!(tty, "{}", prompt)?;
+    tty.flush()?;
+    terminal::enable_raw_mode()?;
+    let answer = read_line(&mut tty, echo);
+    terminal::disable_raw_mode()?;
+    write!(tty, "\r\n")?;
+    answer
+}
+
+fn read_line(tty: &mut File, echo: bool) -> io::Result<String> {
+    let mut answer = String::new();
+    let mut bytes = Vec::new();
+    let mut byte = [0];
+    loop {
+        if tty.read(&mut byte)? == 0 {
+            break;
+        }
+        match byte[0] {
+            b'\r' | b'\n' => break,
+            // Ctrl+C and Ctrl+D
+            3 | 4 => return Err(io::ErrorKind::Interrupted.into()),
+            // Backspace and Delete
+            8 | 127 => {
+                if answer.pop().is_some() && echo {
+                    write!(tty, "\x08 \x08")?;
+                }
+            }
+            b => {
+                bytes.push(b);
+                if let Ok(text) = std::str::from_utf8(&bytes) {
+                    if echo {
+                        write!(tty, "{}", text)?;
+                    }
+                    answer.push_str(text);
+                    bytes.clear();
+                }
+            }
+        }
+    }
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::askpass::{answers, fetch_secret, Askpass};
+
+    #[test]
+    fn test_askpass() {
+        assert!(answers("root@10.0.0.1's password: ", "password"));
+        assert!(answers("(root@web1) Password: ", "password"));
+        assert!(!answers(
+            "Enter passphrase for key '/root/.ssh/id_ed25519': ",
+            "password"
+        ));
+        assert!(answers(
+            "Enter passphrase for /root/.ssh/id_ed25519: ",
+            "passphrase"
+        ));
+        assert!(!answers(
+            "Are you sure you want to continue connecting (yes/no/[fingerprint])? ",
+            "password"
+        ));
+
+        let askpass = Askpass::serve("s3cret pass", "password").unwrap();
+        let socket = askpass.socket.clone();
+        assert_eq!(fetch_secret(&socket).as_deref(), Some("s3cret pass"));
+        // A second prompt means the password was wrong, asking again won't help
+        assert_eq!(fetch_secret(&socket), None);
+
+        let twice = Askpass::serve_times("new pass", "passphrase", 2).unwrap();
+        assert_eq!(fetch_secret(&twice.socket).as_deref(), Some("new pass"));
+        assert_eq!(fetch_secret(&twice.socket).as_deref(), Some("new pass"));
+        assert_eq!(fetch_secret(&twice.socket), None);
+
+        let dir = askpass.dir.clone();
+        drop(askpass);
+        assert!(!dir.exists());
+        assert_eq!(fetch_secret(&socket), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::config_file::write_atomic;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Backups kept per config file, the oldest ones are removed first
+const MAX_BACKUPS: usize = 50;
+// Sortable and precise enough for several writes in a row to get distinct names
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%.6f";
+// Lines shown around each change of a diff
+const DIFF_CONTEXT: usize = 3;
+
+/// A copy of a config file taken before lazyssh wrote to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    /// Where the copy is stored.
+    pub path: PathBuf,
+    /// The config file it is a copy of.
+    pub original: PathBuf,
+    pub time: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Where backups are kept, outside `~/.ssh` so globs in `Include` never pick them up.
+pub fn backup_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join("lazyssh").join("backups"))
+}
+
+/// Backup names hold the path of the original, with `/` escaped.
+fn encode(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F")
+}
+
+fn decode(name: &str) -> PathBuf {
+    PathBuf::from(name.replace("%2F", "/").replace("%25", "%"))
+}
+
+/// Copies `path` into `dir`, unless it doesn't exist, then removes its oldest backups beyond
+/// `MAX_BACKUPS`. Backups are only readable by the user since configs may hold passwords.
+pub fn backup(path: &Path, dir: &Path) -> io::Result<Option<Backup>> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+
+    let time = Local::now();
+    let name = format!("{}_{}", time.format(TIMESTAMP_FORMAT), encode(path));
+    let backup = Backup {
+        path: dir.join(name),
+        original: path.to_path_buf(),
+        time,
+    };
+    fs::write(&backup.path, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&backup.path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    let old_backups = list_backups(dir)?
+        .into_iter()
+        .filter(|old| old.original == path)
+        .skip(MAX_BACKUPS);
+    for old in old_backups {
+        fs::remove_file(old.path)?;
+    }
+    Ok(Some(backup))
+}
+
+/// The backups in `dir`, newest first.
+pub fn list_backups(dir: &Path) -> io::Result<Vec<Backup>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let Some((timestamp, original)) = name.split_once('_') else {
+            continue;
+        };
+        let Ok(time) = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT) else {
+            continue;
+        };
+        let Some(time) = Local.from_local_datetime(&time).earliest() else {
+            continue;
+        };
+        backups.push(Backup {
+            original: decode(original),
+            path: path.clone(),
+            time,
+        });
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.time));
+    Ok(backups)
+}
+
+/// Puts `backup` back in place of its original, backing the current version up first.
+pub fn restore(backup: &Backup, dir: &Path) -> io::Result<()> {
+    let content = fs::read_to_string(&backup.path)?;
+    self::backup(&backup.original, dir)?;
+    write_atomic(&backup.original, &content)
+}
+
+/// The lines to remove from `old` and add to get `new`, from their longest common subsequence.
+pub fn diff<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            lines.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+    lines.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+    lines
+}
+
+/// Renders the changes of `diff` with a few lines of context, `...` marking skipped lines.
+pub fn format_diff(diff: &[DiffLine]) -> String {
+    let changed: Vec<usize> = (0..diff.len())
+        .filter(|&i| !matches!(diff[i], DiffLine::Same(_)))
+        .collect();
+    let near_change = |i: usize| {
+        changed
+            .iter()
+            .any(|&c| c.saturating_sub(DIFF_CONTEXT) <= i && i <= c + DIFF_CONTEXT)
+    };
+
+    let mut output = String::new();
+    let mut skipped = false;
+    for (i, line) in diff.iter().enumerate() {
+        if !near_change(i) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            output.push_str("...\n");
+            skipped = false;
+        }
+        let (prefix, text) = match line {
+            DiffLine::Same(text) => (' ', text),
+            DiffLine::Removed(text) => ('-', text),
+            DiffLine::Added(text) => ('+', text),
+        };
+        output.push_str(&format!("{} {}\n", prefix, text));
+    }
+    if skipped {
+        output.push_str("...\n");
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backup::{backup, diff, format_diff, list_backups, restore, DiffLine, MAX_BACKUPS};
+    use crate::test_util::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn test_backups() {
+        let dir = temp_dir("backups");
+        let config = dir.join("ssh dir/config");
+        let backups = dir.join("backups");
+        fs::create_dir_all(config.parent().unwrap()).unwrap();
+
+        assert_eq!(backup(&config, &backups).unwrap(), None);
+        for i in 0..MAX_BACKUPS + 2 {
+            fs::write(&config, format!("Host v{}\n", i)).unwrap();
+            backup(&config, &backups).unwrap().unwrap();
+        }
+
+        let list = list_backups(&backups).unwrap();
+        assert_eq!(list.len(), MAX_BACKUPS);
+        assert_eq!(list[0].original, config);
+        let last = format!("Host v{}\n", MAX_BACKUPS + 1);
+        assert_eq!(fs::read_to_string(&list[0].path).unwrap(), last);
+
+        // Restoring backs up the current version too
+        fs::write(&config, "Host changed\n").unwrap();
+        restore(&list[1], &backups).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config).unwrap(),
+            format!("Host v{}\n", MAX_BACKUPS)
+        );
+        let list = list_backups(&backups).unwrap();
+        assert_eq!(fs::read_to_string(&list[0].path).unwrap(), "Host changed\n");
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = "Host a\n    HostName 1\nHost b\n    HostName 2\n";
+        let new = "Host a\n    HostName 1\nHost b\n    HostName 3\nHost c\n";
+        assert_eq!(
+            diff(old, new),
+            vec![
+                DiffLine::Same("Host a"),
+                DiffLine::Same("    HostName 1"),
+                DiffLine::Same("Host b"),
+                DiffLine::Removed("    HostName 2"),
+                DiffLine::Added("    HostName 3"),
+                DiffLine::Added("Host c"),
+            ]
+        );
+
+        let old: String = (0..20).map(|i| format!("line {}\n", i)).collect();
+        let new = old.replace("line 10\n", "line ten\n");
+        assert_eq!(
+            format_diff(&diff(&old, &new)),
+            "...\n  line 7\n  line 8\n  line 9\n- line 10\n+ line ten\n  line 11\n  line 12\n  line 13\n...\n"
+        );
+    }
+}
@@ -0,0 +1,1127 @@
+use crate::backup::backup;
+use crate::history::FileChange;
+use crate::server::{
+    group_path, is_in_group, is_wildcard, parent_group, split_keyword, tokenize, ServerItem,
+    ServerList, OTHER_GROUP,
+};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_INDENT: &str = "    ";
+
+/// A config file kept line by line, so edits only touch the lines they are about and comments,
+/// ordering, indentation and directives lazyssh doesn't know about survive a rewrite.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub lines: Vec<String>,
+    crlf: bool,
+    trailing_newline: bool,
+}
+
+/// Line range of a `Host` block: `start` is the `Host` line and `end` the line after its last
+/// directive or marker. Comments and blank lines after that are left to whatever follows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The settings of a host's own block, as edited in the host form. Empty strings are unset.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HostSettings {
+    pub host: String,
+    pub hostname: String,
+    pub user: String,
+    pub port: String,
+    pub identity_file: String,
+    pub group: String,
+    pub password: String,
+}
+
+/// Files loaded for an edit, the ones that changed are written back by `save`.
+#[derive(Debug, Default)]
+pub struct ConfigEditor {
+    files: Vec<ConfigFile>,
+    /// Content of the files when they were loaded, `None` for files that didn't exist.
+    originals: Vec<Option<String>>,
+    /// Where files are backed up before being written, no backups are made if `None`.
+    backup_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+enum LineKind {
+    Blank,
+    Comment,
+    /// `#: Group`, `#: Password`, ... with the lowercase marker name
+    Marker(String),
+    /// `Host` or `Match`
+    Header,
+    /// A directive with its lowercase keyword
+    Directive(String),
+}
+
+fn classify(line: &str) -> LineKind {
+    let line = line.trim();
+    if line.is_empty() {
+        return LineKind::Blank;
+    }
+    if let Some(marker) = line.strip_prefix("#:") {
+        let name = marker.split_whitespace().next().unwrap_or_default();
+        return LineKind::Marker(name.to_lowercase());
+    }
+    if line.starts_with('#') {
+        return LineKind::Comment;
+    }
+    match split_keyword(line).0.to_lowercase().as_str() {
+        "host" | "match" => LineKind::Header,
+        keyword => LineKind::Directive(keyword.to_string()),
+    }
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// The ` # comment` ending a line, found the same way `tokenize` stops at it.
+fn inline_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if previous.is_whitespace() && i > 0 => {
+                let start = line[..i].trim_end().len();
+                return &line[start..];
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    ""
+}
+
+/// Quotes `value` if `tokenize` would otherwise split it or take part of it as a comment.
+fn quote(value: &str) -> String {
+    if value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '#')) {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn is_concrete(pattern: &str) -> bool {
+    !is_wildcard(pattern) && !pattern.starts_with('!')
+}
+
+/// Replaces the file at `path` atomically, writing through symlinks and keeping its permissions
+/// since OpenSSH refuses configs writable by others.
+pub fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp = target.with_file_name(format!(".{}.lazyssh", file_name));
+    // Private from the start, files such as the vault must never be readable by others. A
+    // leftover temp file is replaced rather than reused with its permissions
+    let _ = fs::remove_file(&temp);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&temp)?.write_all(content.as_ref())?;
+    if let Ok(metadata) = fs::metadata(&target) {
+        fs::set_permissions(&temp, metadata.permissions())?;
+    }
+    fs::rename(&temp, &target)
+}
+
+fn not_found(host: &str, path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("`{}` not found in {}", host, path.display()),
+    )
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(path, &fs::read_to_string(path)?))
+    }
+
+    pub fn parse(path: &Path, content: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            lines: content.lines().map(str::to_string).collect(),
+            crlf: content.contains("\r\n"),
+            trailing_newline: content.is_empty() || content.ends_with('\n'),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        write_atomic(&self.path, self.to_string())
+    }
+
+    fn is_host_header(&self, i: usize) -> bool {
+        self.lines.get(i).is_some_and(|line| {
+            tokenize(line)
+                .first()
+                .is_some_and(|keyword| keyword.eq_ignore_ascii_case("host"))
+        })
+    }
+
+    pub fn block(&self, start: usize) -> Option<BlockSpan> {
+        if classify(self.lines.get(start)?) != LineKind::Header {
+            return None;
+        }
+        let mut end = start + 1;
+        for (i, line) in self.lines.iter().enumerate().skip(start + 1) {
+            match classify(line) {
+                LineKind::Header => break,
+                LineKind::Marker(marker) if marker == "group" => break,
+                LineKind::Directive(_) | LineKind::Marker(_) => end = i + 1,
+                LineKind::Blank | LineKind::Comment => {}
+            }
+        }
+        Some(BlockSpan { start, end })
+    }
+
+    fn patterns(&self, start: usize) -> Vec<String> {
+        tokenize(&self.lines[start]).split_off(1)
+    }
+
+    fn is_spaced_name(&self, block: BlockSpan) -> bool {
+        self.marker_line(block, "spacedname").is_some()
+    }
+
+    /// Finds the block of the host `alias`, trying the 1-based `line` it was parsed at first.
+    pub fn find_host(&self, alias: &str, line: usize) -> Option<BlockSpan> {
+        let defines_alias = |start: usize| {
+            if !self.is_host_header(start) {
+                return false;
+            }
+            let patterns = self.patterns(start);
+            patterns.iter().any(|pattern| pattern == alias)
+                || (patterns.join(" ") == alias
+                    && self.block(start).is_some_and(|b| self.is_spaced_name(b)))
+        };
+        let start = if line > 0 && defines_alias(line - 1) {
+            line - 1
+        } else {
+            (0..self.lines.len()).find(|&i| defines_alias(i))?
+        };
+        self.block(start)
+    }
+
+    fn option_lines(&self, block: BlockSpan, keyword: &str) -> Vec<usize> {
+        (block.start + 1..block.end)
+            .filter(|&i| classify(&self.lines[i]) == LineKind::Directive(keyword.to_lowercase()))
+            .collect()
+    }
+
+    fn marker_line(&self, block: BlockSpan, marker: &str) -> Option<usize> {
+        (block.start + 1..block.end)
+            .find(|&i| classify(&self.lines[i]) == LineKind::Marker(marker.to_string()))
+    }
+
+    /// The first value of `keyword` in the block itself.
+    pub fn option(&self, block: BlockSpan, keyword: &str) -> Option<String> {
+        let i = *self.option_lines(block, keyword).first()?;
+        tokenize(&self.lines[i]).into_iter().nth(1)
+    }
+
+    pub fn password(&self, block: BlockSpan) -> Option<String> {
+        let i = self.marker_line(block, "password")?;
+        let password = self.lines[i].trim().strip_prefix("#: Password")?;
+        Some(password.trim().to_string())
+    }
+
+    fn indent(&self, block: BlockSpan) -> String {
+        (block.start + 1..block.end)
+            .map(|i| &self.lines[i])
+            .find(|line| !matches!(classify(line), LineKind::Blank))
+            .map(|line| leading_whitespace(line).to_string())
+            .unwrap_or_else(|| DEFAULT_INDENT.to_string())
+    }
+
+    /// Sets the first `keyword` line of the block keeping its indentation, spelling, separator and
+    /// trailing comment, adds it at the end of the block if missing, or removes all of them for `None`.
+    pub fn set_option(&mut self, block: &mut BlockSpan, keyword: &str, value: Option<&str>) {
+        let lines = self.option_lines(*block, keyword);
+        match (lines.first(), value) {
+            (Some(&i), Some(value)) => {
+                if self.option(*block, keyword).as_deref() == Some(value) {
+                    return;
+                }
+                let line = &self.lines[i];
+                let trimmed = line.trim();
+                let (spelling, rest) = split_keyword(trimmed);
+                // Keeps `Key=Value` or the spacing between them as written
+                let separator = match &trimmed[spelling.len()..trimmed.len() - rest.len()] {
+                    "" => " ",
+                    separator => separator,
+                };
+                self.lines[i] = format!(
+                    "{}{}{}{}{}",
+                    leading_whitespace(line),
+                    spelling,
+                    separator,
+                    quote(value),
+                    inline_comment(line)
+                );
+            }
+            (Some(_), None) => {
+                for &i in lines.iter().rev() {
+                    self.lines.remove(i);
+                    block.end -= 1;
+                }
+            }
+            (None, Some(value)) => {
+                let line = format!("{}{} {}", self.indent(*block), keyword, quote(value));
+                self.lines.insert(block.end, line);
+                block.end += 1;
+            }
+            (None, None) => {}
+        }
+    }
+
+    pub fn set_password(&mut self, block: &mut BlockSpan, password: Option<&str>) {
+        match (self.marker_line(*block, "password"), password) {
+            (Some(i), Some(password)) => {
+                let indent = leading_whitespace(&self.lines[i]).to_string();
+                self.lines[i] = format!("{}#: Password {}", indent, password);
+            }
+            (Some(i), None) => {
+                self.lines.remove(i);
+                block.end -= 1;
+            }
+            (None, Some(password)) => {
+                let line = format!("{}#: Password {}", self.indent(*block), password);
+                self.lines.insert(block.end, line);
+                block.end += 1;
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Removes the `#: <marker>` comments of the block, `marker` being lowercase.
+    pub fn remove_marker(&mut self, block: &mut BlockSpan, marker: &str) {
+        while let Some(i) = self.marker_line(*block, marker) {
+            self.lines.remove(i);
+            block.end -= 1;
+        }
+    }
+
+    fn set_patterns(&mut self, start: usize, patterns: &[String]) {
+        let line = &self.lines[start];
+        let spelling = split_keyword(line.trim()).0;
+        let patterns: Vec<String> = patterns.iter().map(|p| quote(p)).collect();
+        self.lines[start] = format!(
+            "{}{} {}{}",
+            leading_whitespace(line),
+            spelling,
+            patterns.join(" "),
+            inline_comment(line)
+        );
+    }
+
+    pub fn rename_host(&mut self, block: BlockSpan, alias: &str, new_alias: &str) {
+        if self.is_spaced_name(block) {
+            // The name is written unquoted, one pattern per word
+            let patterns: Vec<String> = new_alias.split_whitespace().map(str::to_string).collect();
+            let line = &self.lines[block.start];
+            let spelling = split_keyword(line.trim()).0;
+            self.lines[block.start] = format!(
+                "{}{} {}{}",
+                leading_whitespace(line),
+                spelling,
+                patterns.join(" "),
+                inline_comment(line)
+            );
+            return;
+        }
+        let patterns: Vec<String> = self
+            .patterns(block.start)
+            .into_iter()
+            .map(|p| if p == alias { new_alias.to_string() } else { p })
+            .collect();
+        self.set_patterns(block.start, &patterns);
+    }
+
+    /// Whether `alias` shares its block with other hosts or templates.
+    fn shares_block(&self, block: BlockSpan) -> bool {
+        !self.is_spaced_name(block) && self.patterns(block.start).len() > 1
+    }
+
+    /// Moves `alias` out of a `Host` line listing several patterns into a copy of the block
+    /// right after it, so editing it doesn't affect the others.
+    pub fn split_host(&mut self, block: BlockSpan, alias: &str) -> BlockSpan {
+        if !self.shares_block(block) {
+            return block;
+        }
+        let mut lines = self.lines[block.start..block.end].to_vec();
+        self.remove_pattern(block.start, alias);
+        let header = &lines[0];
+        let spelling = split_keyword(header.trim()).0;
+        lines[0] = format!(
+            "{}{} {}",
+            leading_whitespace(header),
+            spelling,
+            quote(alias)
+        );
+        let len = lines.len();
+        self.insert_lines(block.end, lines);
+        BlockSpan {
+            start: block.end,
+            end: block.end + len,
+        }
+    }
+
+    fn remove_pattern(&mut self, start: usize, alias: &str) {
+        let patterns: Vec<String> = self
+            .patterns(start)
+            .into_iter()
+            .filter(|p| p != alias)
+            .collect();
+        self.set_patterns(start, &patterns);
+    }
+
+    /// Removes `alias` from its `Host` line, or the whole block if it is the only host of it.
+    /// Returns the removed lines, or `None` if only the alias was removed.
+    pub fn remove_host(&mut self, block: BlockSpan, alias: &str) -> Option<Vec<String>> {
+        if self.shares_block(block) {
+            self.remove_pattern(block.start, alias);
+            return None;
+        }
+        Some(self.lines.drain(block.start..block.end).collect())
+    }
+
+    pub fn insert_lines(&mut self, at: usize, lines: Vec<String>) {
+        self.lines.splice(at..at, lines);
+    }
+
+    /// The group of the `#: Group` marker on line `i`.
+    fn marker_group(&self, i: usize) -> Option<String> {
+        let name = self.lines.get(i)?.trim().strip_prefix("#: Group")?;
+        Some(group_path(name))
+    }
+
+    pub fn group_marker(&self, group: &str) -> Option<usize> {
+        (0..self.lines.len()).find(|&i| self.marker_group(i).is_some_and(|name| name == group))
+    }
+
+    /// The marker of `group` at the 1-based `line` it was parsed at, or the first one.
+    fn group_marker_at(&self, group: &str, line: usize) -> Option<usize> {
+        if line > 0
+            && self
+                .marker_group(line - 1)
+                .is_some_and(|name| name == group)
+        {
+            return Some(line - 1);
+        }
+        self.group_marker(group)
+    }
+
+    /// The lines of `group` and the groups nested in it, `None` if their markers are mixed with
+    /// markers of other groups.
+    pub fn subtree_span(&self, group: &str) -> Option<BlockSpan> {
+        let markers: Vec<(usize, String)> = (0..self.lines.len())
+            .filter_map(|i| self.marker_group(i).map(|name| (i, name)))
+            .collect();
+        let run: Vec<usize> = (0..markers.len())
+            .filter(|&k| is_in_group(&markers[k].1, group))
+            .collect();
+        let (&first, &last) = (run.first()?, run.last()?);
+        if last - first + 1 != run.len() {
+            return None;
+        }
+        Some(BlockSpan {
+            start: markers[first].0,
+            end: self.group_span(markers[last].0).end,
+        })
+    }
+
+    /// The lines of the group starting at `marker`: the marker and its hosts, but not the defaults
+    /// such as `Host *` that may follow the last of them.
+    pub fn group_span(&self, marker: usize) -> BlockSpan {
+        BlockSpan {
+            start: marker,
+            end: self.insert_position(Some(marker)),
+        }
+    }
+
+    /// Swaps the lines of `first` and `second`, `first` coming before `second`.
+    pub fn swap_spans(&mut self, first: BlockSpan, second: BlockSpan) {
+        let second_lines: Vec<String> = self.lines.drain(second.start..second.end).collect();
+        let first_lines: Vec<String> = self.lines.drain(first.start..first.end).collect();
+        let first_len = first_lines.len();
+        let second_len = second_lines.len();
+        self.insert_lines(first.start, second_lines);
+        self.insert_lines(second.start - first_len + second_len, first_lines);
+    }
+
+    fn defines_concrete_host(&self, i: usize) -> bool {
+        self.is_host_header(i) && self.patterns(i).iter().any(|p| is_concrete(p))
+    }
+
+    /// Where a new host of the section following `marker` (or starting the file) goes: after the
+    /// last host of the section, which keeps it ahead of trailing defaults such as `Host *`.
+    pub fn insert_position(&self, marker: Option<usize>) -> usize {
+        let start = marker.map_or(0, |marker| marker + 1);
+        let end = (start..self.lines.len())
+            .find(|&i| classify(&self.lines[i]) == LineKind::Marker("group".to_string()))
+            .unwrap_or(self.lines.len());
+        let mut last_host_end = None;
+        let mut first_header = None;
+        for i in start..end {
+            if classify(&self.lines[i]) != LineKind::Header {
+                continue;
+            }
+            first_header.get_or_insert(i);
+            if self.defines_concrete_host(i) {
+                last_host_end = self.block(i).map(|block| block.end);
+            }
+        }
+        last_host_end
+            .or(marker.map(|marker| marker + 1))
+            .or(first_header)
+            .unwrap_or(end)
+    }
+
+    /// Where a new group goes: after the last host or group of the file.
+    pub fn append_position(&self) -> usize {
+        let last_host = (0..self.lines.len())
+            .rev()
+            .find(|&i| self.defines_concrete_host(i))
+            .and_then(|i| self.block(i))
+            .map(|block| block.end);
+        let last_group = (0..self.lines.len())
+            .rev()
+            .find(|&i| classify(&self.lines[i]) == LineKind::Marker("group".to_string()))
+            .map(|i| i + 1);
+        last_host
+            .max(last_group)
+            .or_else(|| {
+                (0..self.lines.len()).find(|&i| classify(&self.lines[i]) == LineKind::Header)
+            })
+            .unwrap_or(self.lines.len())
+    }
+}
+
+impl fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        write!(f, "{}", self.lines.join(newline))?;
+        if self.trailing_newline && !self.lines.is_empty() {
+            write!(f, "{}", newline)?;
+        }
+        Ok(())
+    }
+}
+
+impl HostSettings {
+    /// The values of the host's own block, not the ones inherited from wildcard blocks.
+    pub fn from_server(server: &ServerItem) -> io::Result<Self> {
+        let Some(source) = &server.source else {
+            return Err(not_found(&server.host, Path::new("the config")));
+        };
+        let file = ConfigFile::load(source)?;
+        let block = file
+            .find_host(&server.host, server.line)
+            .ok_or_else(|| not_found(&server.host, source))?;
+        let group = if server.group == OTHER_GROUP {
+            String::new()
+        } else {
+            server.group.clone()
+        };
+        Ok(Self {
+            host: server.host.clone(),
+            hostname: file.option(block, "hostname").unwrap_or_default(),
+            user: file.option(block, "user").unwrap_or_default(),
+            port: file.option(block, "port").unwrap_or_default(),
+            identity_file: file.option(block, "identityfile").unwrap_or_default(),
+            group,
+            password: file.password(block).unwrap_or_default(),
+        })
+    }
+
+    fn group(&self) -> &str {
+        match self.group.trim() {
+            "" => OTHER_GROUP,
+            group => group,
+        }
+    }
+
+    fn apply(&self, file: &mut ConfigFile, block: &mut BlockSpan) {
+        fn value(value: &str) -> Option<&str> {
+            Some(value.trim()).filter(|value| !value.is_empty())
+        }
+        file.set_option(block, "HostName", value(&self.hostname));
+        file.set_option(block, "User", value(&self.user));
+        file.set_option(block, "Port", value(&self.port));
+        file.set_option(block, "IdentityFile", value(&self.identity_file));
+        file.set_password(block, value(&self.password));
+    }
+}
+
+impl ConfigEditor {
+    pub fn with_backups(backup_dir: Option<PathBuf>) -> Self {
+        Self {
+            backup_dir,
+            ..Self::default()
+        }
+    }
+
+    fn file(&mut self, path: &Path) -> io::Result<&mut ConfigFile> {
+        let i = match self.files.iter().position(|file| file.path == path) {
+            Some(i) => i,
+            None => {
+                let content = match fs::read_to_string(path) {
+                    Ok(content) => Some(content),
+                    // Adding the first host creates the config
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(e),
+                };
+                let file = ConfigFile::parse(path, content.as_deref().unwrap_or_default());
+                self.originals.push(content);
+                self.files.push(file);
+                self.files.len() - 1
+            }
+        };
+        Ok(&mut self.files[i])
+    }
+
+    /// Backs up and writes the files that changed, returning what changed.
+    pub fn save(self) -> io::Result<Vec<FileChange>> {
+        let mut changes = Vec::new();
+        for (file, original) in self.files.into_iter().zip(self.originals) {
+            let content = file.to_string();
+            if original.as_ref() == Some(&content) {
+                continue;
+            }
+            if let Some(dir) = &self.backup_dir {
+                backup(&file.path, dir)?;
+            }
+            file.save()?;
+            changes.push(FileChange {
+                path: file.path,
+                before: original,
+                after: content,
+            });
+        }
+        Ok(changes)
+    }
+
+    pub fn add_host(&mut self, list: &ServerList, settings: &HostSettings) -> io::Result<()> {
+        let mut file = ConfigFile::parse(Path::new(""), "");
+        file.lines
+            .push(format!("Host {}", quote(settings.host.trim())));
+        let mut block = file.block(0).unwrap();
+        settings.apply(&mut file, &mut block);
+        self.insert_into_group(list, settings.group(), file.lines)
+    }
+
+    /// The file `server` is defined in and its block there.
+    fn host_block(&mut self, server: &ServerItem) -> io::Result<(&mut ConfigFile, BlockSpan)> {
+        let source = server
+            .source
+            .as_deref()
+            .ok_or_else(|| not_found(&server.host, Path::new("the config")))?;
+        let file = self.file(source)?;
+        let block = file
+            .find_host(&server.host, server.line)
+            .ok_or_else(|| not_found(&server.host, source))?;
+        Ok((file, block))
+    }
+
+    /// Removes the block of `server` from its file, returning its lines.
+    fn take_host(&mut self, server: &ServerItem) -> io::Result<Vec<String>> {
+        let (file, block) = self.host_block(server)?;
+        let block = file.split_host(block, &server.host);
+        Ok(file.lines.drain(block.start..block.end).collect())
+    }
+
+    pub fn update_host(
+        &mut self,
+        list: &ServerList,
+        server: &ServerItem,
+        settings: &HostSettings,
+    ) -> io::Result<()> {
+        let (file, block) = self.host_block(server)?;
+        let mut block = file.split_host(block, &server.host);
+        let new_host = settings.host.trim();
+        if new_host != server.host {
+            file.rename_host(block, &server.host, new_host);
+        }
+        settings.apply(file, &mut block);
+
+        if settings.group() != server.group {
+            let lines = file.lines.drain(block.start..block.end).collect();
+            self.insert_into_group(list, settings.group(), lines)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_host(&mut self, server: &ServerItem) -> io::Result<()> {
+        let (file, block) = self.host_block(server)?;
+        file.remove_host(block, &server.host);
+        Ok(())
+    }
+
+    /// Removes the `#: Password` comment from the block of `server`, once the vault holds it.
+    pub fn remove_password(&mut self, server: &ServerItem) -> io::Result<()> {
+        let (file, mut block) = self.host_block(server)?;
+        file.set_password(&mut block, None);
+        Ok(())
+    }
+
+    /// Makes `server` log in with `identity_file` instead of a password, once its public key was
+    /// installed on the host.
+    pub fn use_identity_file(
+        &mut self,
+        server: &ServerItem,
+        identity_file: &str,
+    ) -> io::Result<()> {
+        let (file, block) = self.host_block(server)?;
+        let mut block = file.split_host(block, &server.host);
+        file.set_option(&mut block, "IdentityFile", Some(identity_file));
+        file.set_password(&mut block, None);
+        file.remove_marker(&mut block, "passwordfrom");
+        Ok(())
+    }
+
+    /// Moves the blocks of `servers` after the block of `target`, or at the end of its group if
+    /// `target` is a group.
+    pub fn move_hosts(
+        &mut self,
+        list: &ServerList,
+        servers: &[ServerItem],
+        target: &ServerItem,
+    ) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for server in servers {
+            lines.extend(self.take_host(server)?);
+        }
+        if target.is_group {
+            return self.insert_into_group(list, &target.group, lines);
+        }
+        let (file, block) = self.host_block(target)?;
+        file.insert_lines(block.end, lines);
+        Ok(())
+    }
+
+    pub fn add_group(&mut self, list: &ServerList, group: &str) -> io::Result<()> {
+        let file = self.file(&list.config_path)?;
+        let at = file.append_position();
+        file.insert_lines(at, vec![format!("#: Group {}", group)]);
+        Ok(())
+    }
+
+    /// Rewrites every `#: Group` marker of `group` and of the groups nested in it.
+    pub fn rename_group(&mut self, list: &ServerList, group: &str, name: &str) -> io::Result<()> {
+        for item in list.subtree_markers(group) {
+            let Some(source) = &item.source else {
+                continue;
+            };
+            let file = self.file(source)?;
+            let i = file
+                .group_marker_at(&item.group, item.line)
+                .ok_or_else(|| not_found(&item.group, source))?;
+            let indent = leading_whitespace(&file.lines[i]).to_string();
+            let renamed = format!("{}{}", name, &item.group[group.len()..]);
+            file.lines[i] = format!("{}#: Group {}", indent, renamed);
+        }
+        Ok(())
+    }
+
+    /// Removes the markers of `group` and of the groups nested in it, their hosts move to the
+    /// parent group.
+    pub fn delete_group(&mut self, list: &ServerList, group: &str) -> io::Result<()> {
+        let hosts: Vec<ServerItem> = list
+            .items
+            .iter()
+            .filter(|item| !item.is_group && is_in_group(&item.group, group))
+            .cloned()
+            .collect();
+        let mut lines = Vec::new();
+        for host in &hosts {
+            lines.extend(self.take_host(host)?);
+        }
+        // Bottom up so the line numbers of the markers left stay valid
+        for item in list.subtree_markers(group).iter().rev() {
+            let Some(source) = &item.source else {
+                continue;
+            };
+            let file = self.file(source)?;
+            if let Some(i) = file.group_marker_at(&item.group, item.line) {
+                file.lines.remove(i);
+            }
+        }
+        let parent = match parent_group(group) {
+            "" => OTHER_GROUP,
+            parent => parent,
+        };
+        self.insert_into_group(list, parent, lines)
+    }
+
+    /// Swaps `group` and its subgroups with the group before it in the same parent, or after it
+    /// if `down`. Returns `false` if there is no such group in the same file.
+    pub fn move_group(&mut self, list: &ServerList, group: &str, down: bool) -> io::Result<bool> {
+        let Some(sibling) = list.sibling_group(group, down) else {
+            return Ok(false);
+        };
+        let sources = |group: &str| {
+            let mut sources: Vec<&Path> = list
+                .subtree_markers(group)
+                .iter()
+                .filter_map(|item| item.source.as_deref())
+                .collect();
+            sources.dedup();
+            sources
+        };
+        let source = sources(group);
+        if source.len() != 1 || source != sources(&sibling) {
+            return Ok(false);
+        }
+
+        let file = self.file(source[0])?;
+        let (Some(a), Some(b)) = (file.subtree_span(group), file.subtree_span(&sibling)) else {
+            return Err(io::Error::other(format!(
+                "the groups nested in `{}` or `{}` are not next to each other",
+                group, sibling
+            )));
+        };
+        let (first, second) = if a.start < b.start { (a, b) } else { (b, a) };
+        file.swap_spans(first, second);
+        Ok(true)
+    }
+
+    /// Inserts a host block at the end of `group`, creating the group if it doesn't exist.
+    fn insert_into_group(
+        &mut self,
+        list: &ServerList,
+        group: &str,
+        lines: Vec<String>,
+    ) -> io::Result<()> {
+        if group == OTHER_GROUP {
+            let file = self.file(&list.config_path)?;
+            let at = file.insert_position(None);
+            file.insert_lines(at, lines);
+            return Ok(());
+        }
+
+        let marker = list
+            .items
+            .iter()
+            .find(|item| item.is_group && item.group == group && item.source.is_some())
+            .and_then(|item| item.source.clone());
+        match marker {
+            Some(source) => {
+                let file = self.file(&source)?;
+                let marker = file.group_marker(group);
+                let at = file.insert_position(marker);
+                file.insert_lines(at, lines);
+            }
+            None => {
+                let file = self.file(&list.config_path)?;
+                let at = file.append_position();
+                file.insert_lines(at, vec![format!("#: Group {}", group)]);
+                file.insert_lines(at + 1, lines);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config_file::{write_atomic, ConfigEditor, ConfigFile, HostSettings};
+    use crate::server::{ServerList, OTHER_GROUP};
+    use crate::test_util::{fixture, temp_dir};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    const CONFIG: &str = "\
+# Managed by hand
+Host *.lab
+    User lab
+
+#: Group Dev
+Host dev1 dev2   # both build boxes
+    HostName 10.0.1.1
+    ForwardAgent yes
+    #: Password secret
+Host dev3
+\tHostName=10.0.1.3
+\tPort 2200 # not 22
+
+#: Group Prod
+Host prod1
+    HostName 10.0.2.1
+
+Host *
+    User root
+";
+
+    /// Copies `content` to a file of its own so tests can run in parallel.
+    fn config(name: &str, content: &str) -> PathBuf {
+        let path = temp_dir(name).join("config");
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn edit(path: &Path, f: impl FnOnce(&mut ConfigEditor, &ServerList)) -> String {
+        let list = ServerList::from_config_file(path);
+        let mut editor = ConfigEditor::default();
+        f(&mut editor, &list);
+        editor.save().unwrap();
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_atomic() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = config("atomic", "Host a\n");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        // New files are private, existing ones keep their permissions
+        let new = path.with_file_name("new");
+        write_atomic(&new, "secret").unwrap();
+        assert_eq!(mode(&new), 0o600);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_atomic(&path, "Host b\n").unwrap();
+        assert_eq!(mode(&path), 0o644);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Host b\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let file = ConfigFile::parse(Path::new("config"), CONFIG);
+        assert_eq!(file.to_string(), CONFIG);
+
+        let crlf = "Host a\r\n    HostName b";
+        assert_eq!(
+            ConfigFile::parse(Path::new("config"), crlf).to_string(),
+            crlf
+        );
+    }
+
+    #[test]
+    fn test_edit_host() {
+        let path = config("edit", CONFIG);
+        let content = edit(&path, |editor, list| {
+            let server = list.items.iter().find(|i| i.host == "dev3").unwrap();
+            let mut settings = HostSettings::from_server(server).unwrap();
+            assert_eq!(settings.hostname, "10.0.1.3");
+            assert_eq!(settings.port, "2200");
+            settings.host = "dev4".to_string();
+            settings.hostname = "10.0.1.4".to_string();
+            settings.port = "2201".to_string();
+            settings.user = "deploy".to_string();
+            settings.identity_file = "~/.ssh/My Keys/dev".to_string();
+            editor.update_host(list, server, &settings).unwrap();
+        });
+        assert!(content.contains(
+            "Host dev4\n\
+             \tHostName=10.0.1.4\n\
+             \tPort 2201 # not 22\n\
+             \tUser deploy\n\
+             \tIdentityFile \"~/.ssh/My Keys/dev\"\n\
+             \n#: Group Prod"
+        ));
+        let list = ServerList::from_config_file(&path);
+        let dev4 = list.items.iter().find(|i| i.host == "dev4").unwrap();
+        assert_eq!(dev4.private_key.as_deref(), Some("~/.ssh/My Keys/dev"));
+    }
+
+    #[test]
+    fn test_edit_shared_block() {
+        let path = config("shared", CONFIG);
+        let content = edit(&path, |editor, list| {
+            let server = list.items.iter().find(|i| i.host == "dev2").unwrap();
+            let mut settings = HostSettings::from_server(server).unwrap();
+            settings.hostname = "10.0.1.2".to_string();
+            settings.password.clear();
+            editor.update_host(list, server, &settings).unwrap();
+        });
+        assert!(content.contains(
+            "Host dev1   # both build boxes\n\
+             \x20   HostName 10.0.1.1\n\
+             \x20   ForwardAgent yes\n\
+             \x20   #: Password secret\n\
+             Host dev2\n\
+             \x20   HostName 10.0.1.2\n\
+             \x20   ForwardAgent yes\n\
+             Host dev3\n"
+        ));
+    }
+
+    #[test]
+    fn test_add_and_move_hosts() {
+        let path = config("add", CONFIG);
+        let content = edit(&path, |editor, list| {
+            let settings = HostSettings {
+                host: "prod2".to_string(),
+                hostname: "10.0.2.2".to_string(),
+                group: "Prod".to_string(),
+                ..HostSettings::default()
+            };
+            editor.add_host(list, &settings).unwrap();
+            let settings = HostSettings {
+                host: "box".to_string(),
+                ..HostSettings::default()
+            };
+            editor.add_host(list, &settings).unwrap();
+        });
+        // New hosts go after the last host of their group, ahead of `Host *`
+        assert!(content.contains(
+            "Host prod1\n    HostName 10.0.2.1\nHost prod2\n    HostName 10.0.2.2\n\nHost *\n"
+        ));
+        assert!(content.starts_with("# Managed by hand\nHost box\nHost *.lab\n"));
+
+        let content = edit(&path, |editor, list| {
+            let server = list.items.iter().find(|i| i.host == "prod1").unwrap();
+            let mut settings = HostSettings::from_server(server).unwrap();
+            settings.group = "Staging".to_string();
+            editor.update_host(list, server, &settings).unwrap();
+        });
+        assert!(content.contains(
+            "Host prod2\n    HostName 10.0.2.2\n#: Group Staging\nHost prod1\n    HostName 10.0.2.1\n\nHost *\n"
+        ));
+        let list = ServerList::from_config_file(&path);
+        let prod1 = list.items.iter().find(|i| i.host == "prod1").unwrap();
+        assert_eq!(prod1.group, "Staging");
+    }
+
+    #[test]
+    fn test_delete_host() {
+        let path = config("delete", CONFIG);
+        let content = edit(&path, |editor, list| {
+            for host in ["dev1", "prod1"] {
+                let server = list.items.iter().find(|i| i.host == host).unwrap();
+                editor.delete_host(server).unwrap();
+            }
+        });
+        assert!(content.contains("#: Group Dev\nHost dev2   # both build boxes\n"));
+        assert!(content.contains("#: Group Prod\n\nHost *\n"));
+    }
+
+    #[test]
+    fn test_remove_password() {
+        let path = config("password", CONFIG);
+        let content = edit(&path, |editor, list| {
+            // Both aliases of the block had the password
+            for host in ["dev1", "dev2"] {
+                let server = list.items.iter().find(|i| i.host == host).unwrap();
+                assert_eq!(server.password.as_deref(), Some("secret"));
+                editor.remove_password(server).unwrap();
+            }
+        });
+        assert!(!content.contains("#: Password"));
+        assert!(content.contains("    ForwardAgent yes\nHost dev3\n"));
+    }
+
+    #[test]
+    fn test_use_identity_file() {
+        let path = config("identity", CONFIG);
+        let content = edit(&path, |editor, list| {
+            let server = list.items.iter().find(|i| i.host == "dev2").unwrap();
+            editor.use_identity_file(server, "~/.ssh/dev").unwrap();
+        });
+        // Only dev2 moves to the key, dev1 keeps its password
+        assert!(content.contains(
+            "Host dev1   # both build boxes\n\
+             \x20   HostName 10.0.1.1\n\
+             \x20   ForwardAgent yes\n\
+             \x20   #: Password secret\n\
+             Host dev2\n\
+             \x20   HostName 10.0.1.1\n\
+             \x20   ForwardAgent yes\n\
+             \x20   IdentityFile ~/.ssh/dev\n\
+             Host dev3\n"
+        ));
+    }
+
+    #[test]
+    fn test_groups() {
+        let path = config("groups", CONFIG);
+        edit(&path, |editor, list| {
+            editor.rename_group(list, "Dev", "Build").unwrap();
+        });
+        let content = edit(&path, |editor, list| {
+            assert!(editor.move_group(list, "Prod", false).unwrap());
+        });
+        // Defaults following the last group stay at the end
+        assert!(content.contains(
+            "#: Group Prod\n\
+             Host prod1\n    HostName 10.0.2.1\n\
+             \n#: Group Build\n\
+             Host dev1 dev2"
+        ));
+        assert!(content.ends_with("\tPort 2200 # not 22\n\nHost *\n    User root\n"));
+        edit(&path, |editor, list| {
+            assert!(!editor.move_group(list, "Prod", false).unwrap());
+        });
+
+        let content = edit(&path, |editor, list| {
+            editor.add_group(list, "Staging").unwrap();
+            let prod1 = list.items.iter().find(|i| i.host == "prod1").unwrap();
+            let dev3 = list.items.iter().find(|i| i.host == "dev3").unwrap();
+            editor
+                .move_hosts(list, std::slice::from_ref(prod1), dev3)
+                .unwrap();
+        });
+        assert!(content.contains("#: Group Prod\n\n#: Group Build\n"));
+        assert!(
+            content.contains("Host dev3\n\tHostName=10.0.1.3\n\tPort 2200 # not 22\nHost prod1\n")
+        );
+        assert!(content.contains("    HostName 10.0.2.1\n#: Group Staging\n\nHost *\n"));
+
+        let content = edit(&path, |editor, list| {
+            editor.delete_group(list, "Build").unwrap();
+        });
+        let list = ServerList::from_config_file(&path);
+        for host in ["dev1", "dev2", "dev3", "prod1"] {
+            let item = list.items.iter().find(|i| i.host == host).unwrap();
+            assert_eq!(item.group, OTHER_GROUP);
+        }
+        assert!(!content.contains("#: Group Build"));
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let nested = fs::read_to_string(fixture("nested/config")).unwrap();
+        let path = config("nested", &nested);
+        let content = edit(&path, |editor, list| {
+            editor.rename_group(list, "Prod", "Production").unwrap();
+        });
+        assert!(content.contains("#: Group Production/EU/Web\n"));
+        assert!(content.contains("#: Group Production/US\n"));
+        assert!(content.contains("#: Group Production\n"));
+
+        let content = edit(&path, |editor, list| {
+            assert!(editor.move_group(list, "Production/US", false).unwrap());
+        });
+        assert!(content.contains(
+            "#: Group Production/US\nHost web-us1\n    HostName 10.3.0.1\n\n#: Group Dev\n"
+        ));
+        assert!(
+            content.contains("Host dev1\n    HostName 10.2.0.1\n\n#: Group Production/EU/Web\n")
+        );
+
+        let content = edit(&path, |editor, list| {
+            editor.delete_group(list, "Production/EU").unwrap();
+        });
+        assert!(!content.contains("EU"));
+        assert!(content.ends_with(
+            "Host prod-bastion\n    HostName 10.0.0.1\nHost web-eu1\n    HostName 10.1.0.1\n"
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::secret::{password, SecretProviders};
+use crate::server::ServerItem;
+use crate::ssh_login::{ssh_args, ssh_command};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+// A host that doesn't answer shouldn't freeze the interface for long
+const CONNECT_TIMEOUT: u32 = 10;
+
+/// Appends the key read on stdin to `~/.ssh/authorized_keys` unless it is already there, like
+/// `ssh-copy-id`. Run by `sh` whatever the login shell is, so it only uses double quotes to fit
+/// in a single-quoted argument.
+const INSTALL_SCRIPT: &str = "umask 077; \
+    mkdir -p ~/.ssh && touch ~/.ssh/authorized_keys && read -r key && \
+    { [ ! -s ~/.ssh/authorized_keys ] || [ -z \"$(tail -c 1 ~/.ssh/authorized_keys)\" ] \
+    || echo >> ~/.ssh/authorized_keys; } && \
+    { grep -qxF \"$key\" ~/.ssh/authorized_keys \
+    || printf \"%s\\n\" \"$key\" >> ~/.ssh/authorized_keys; }";
+
+/// A key installed on a host in the background.
+#[derive(Debug)]
+pub struct Deployment {
+    pub server: ServerItem,
+    /// The private key, as given.
+    pub identity_file: String,
+    pub result: io::Result<()>,
+}
+
+/// The arguments of the ssh command installing a key on `server`.
+fn deploy_args(server: &ServerItem, config: &Path) -> Vec<String> {
+    let mut args = vec![
+        "-o".to_string(),
+        format!("ConnectTimeout={}", CONNECT_TIMEOUT),
+    ];
+    // Nobody can answer prompts, lazyssh answers the password one itself
+    if server.password.is_none() {
+        args.push("-o".to_string());
+        args.push("BatchMode=yes".to_string());
+    }
+    args.extend(ssh_args(server, config));
+    args.push(format!("exec sh -c '{}'", INSTALL_SCRIPT));
+    args
+}
+
+/// Adds `public_key`, a line of a `.pub` file, to the `authorized_keys` of `server`, logging in
+/// with its password.
+pub fn deploy_key(server: &ServerItem, config: &Path, public_key: &str) -> io::Result<()> {
+    let mut server = server.clone();
+    server.password = password(&server, &SecretProviders::default())?;
+    let args = deploy_args(&server, config);
+    let (mut command, askpass) = ssh_command(&server, &args, false)?;
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{}", public_key)?;
+    }
+    let output = child.wait_with_output()?;
+    drop(askpass);
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = stderr
+        .lines()
+        .rfind(|line| !line.trim().is_empty())
+        .unwrap_or("ssh failed");
+    Err(io::Error::other(error.to_string()))
+}
+
+/// Runs `deploy_key` on a thread of its own, sending the outcome to `results`.
+pub fn spawn_deploy<T: From<Deployment> + Send + 'static>(
+    server: ServerItem,
+    config: PathBuf,
+    identity_file: String,
+    public_key: String,
+    results: Sender<T>,
+) {
+    thread::spawn(move || {
+        let mut server = server;
+        let result = deploy_key(&server, &config, &public_key);
+        // Only needed to log in
+        server.password = None;
+        let _ = results.send(
+            Deployment {
+                server,
+                identity_file,
+                result,
+            }
+            .into(),
+        );
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use crate::deploy::{deploy_args, INSTALL_SCRIPT};
+    use crate::server::ServerItem;
+    use crate::test_util::temp_dir;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn test_deploy_args() {
+        let server = ServerItem {
+            group: "Dev".to_string(),
+            is_group: false,
+            host: "web".to_string(),
+            ip: Some("10.0.0.1".to_string()),
+            username: Some("root".to_string()),
+            port: 22,
+            private_key: None,
+            identity_files: Vec::new(),
+            forwards: Vec::new(),
+            proxy_jump: None,
+            proxy_command: None,
+            password: Some("secret".to_string()),
+            password_from: None,
+            source: Some("config".into()),
+            line: 1,
+        };
+        let args = deploy_args(&server, Path::new("config"));
+        assert_eq!(
+            args[..5],
+            ["-o", "ConnectTimeout=10", "-F", "config", "web"]
+        );
+        assert!(args[5].starts_with("exec sh -c 'umask 077;"));
+        assert!(!INSTALL_SCRIPT.contains('\''));
+
+        let server = ServerItem {
+            password: None,
+            ..server
+        };
+        assert!(deploy_args(&server, Path::new("config")).contains(&"BatchMode=yes".to_string()));
+    }
+
+    #[test]
+    fn test_install_script() {
+        let home = temp_dir("deploy");
+        fs::create_dir_all(home.join(".ssh")).unwrap();
+        let authorized_keys = home.join(".ssh/authorized_keys");
+        // Written by hand without a final newline
+        fs::write(&authorized_keys, "ssh-ed25519 AAAA old").unwrap();
+        let install = |key: &str| {
+            let mut child = Command::new("sh")
+                .arg("-c")
+                .arg(INSTALL_SCRIPT)
+                .env("HOME", &home)
+                .stdin(Stdio::piped())
+                .spawn()
+                .unwrap();
+            writeln!(child.stdin.take().unwrap(), "{}", key).unwrap();
+            assert!(child.wait().unwrap().success());
+        };
+
+        install("ssh-ed25519 BBBB new key");
+        install("ssh-ed25519 BBBB new key");
+        assert_eq!(
+            fs::read_to_string(&authorized_keys).unwrap(),
+            "ssh-ed25519 AAAA old\nssh-ed25519 BBBB new key\n"
+        );
+        let _ = fs::remove_dir_all(&home);
+    }
+}
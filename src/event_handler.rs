@@ -1,11 +1,34 @@
-use crate::app::App;
+use crate::app::{App, View};
+use crate::popup::{InputAction, Popup};
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEvent, MouseEventKind};
+use ratatui::layout::Position;
 use std::time::Duration;
 
 pub fn handle_key(app: &mut App, key: KeyEvent) {
     if key.kind != KeyEventKind::Press {
         return;
     }
+    app.message = None;
+
+    if app.popup.is_some() {
+        handle_popup_key(app, key);
+        return;
+    }
+
+    if app.view == View::Tunnels {
+        handle_tunnels_key(app, key);
+        return;
+    }
+
+    if app.view == View::Keys {
+        handle_keys_key(app, key);
+        return;
+    }
+
+    if app.view == View::KnownHosts {
+        handle_known_hosts_key(app, key);
+        return;
+    }
 
     if app.is_searching {
         match key.code {
@@ -38,14 +61,7 @@ pub fn handle_key(app: &mut App, key: KeyEvent) {
             // execute ssh login or toggle group
             KeyCode::Enter => {
                 app.is_searching = false;
-                if let Some(server) = app.server_list.selected() {
-                    if server.is_group {
-                        app.server_list.toggle_group();
-                    } else {
-                        app.should_exit = true;
-                        app.has_selected = true;
-                    }
-                }
+                app.login_selected();
             }
             _ => {}
         }
@@ -63,44 +79,160 @@ pub fn handle_key(app: &mut App, key: KeyEvent) {
             app.search_query.clear();
         }
         KeyCode::Char('z') => app.server_list.toggle_all_groups(),
-        KeyCode::Enter => {
-            if let Some(server) = app.server_list.selected() {
-                if server.is_group {
-                    app.server_list.toggle_group();
-                } else {
-                    app.should_exit = true;
-                    app.has_selected = true;
-                }
-            }
-        }
+        KeyCode::Char('w') => app.show_diagnostics = !app.show_diagnostics,
+        KeyCode::Char('t') => app.toggle_tunnel(),
+        KeyCode::Char('T') => app.view = View::Tunnels,
+        KeyCode::Char('i') => app.show_keys(),
+        KeyCode::Char('H') => app.show_known_hosts(),
+        KeyCode::Char('R') => app.forget_selected_host_keys(),
+        KeyCode::Char('a') => app.add_host(),
+        KeyCode::Char('A') => app.add_selected_key(),
+        KeyCode::Char('c') => app.deploy_key_selected(),
+        KeyCode::Char('e') => app.edit_selected_host(),
+        KeyCode::Char('d') => app.delete_selected(),
+        KeyCode::Char('n') => app.add_group(),
+        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => app.redo(),
+        KeyCode::Char('r') => app.rename_selected_group(),
+        KeyCode::Char('J') => app.move_selected_group(true),
+        KeyCode::Char('K') => app.move_selected_group(false),
+        KeyCode::Char('u') => app.undo(),
+        KeyCode::Char('x') => app.toggle_cut(),
+        KeyCode::Char('p') => app.paste(),
+        KeyCode::Esc if !app.clipboard.is_empty() => app.clipboard.clear(),
+        KeyCode::Esc if app.show_diagnostics => app.show_diagnostics = false,
+        KeyCode::Enter => app.login_selected(),
+        _ => {}
+    }
+}
+
+fn handle_popup_key(app: &mut App, key: KeyEvent) {
+    match &mut app.popup {
+        Some(Popup::HostForm(form)) => match key.code {
+            KeyCode::Esc => app.popup = None,
+            KeyCode::Enter => app.save_host_form(),
+            KeyCode::Tab | KeyCode::Down => form.focus_next(),
+            KeyCode::BackTab | KeyCode::Up => form.focus_previous(),
+            KeyCode::Backspace => form.pop(),
+            KeyCode::Char(c) => form.push(c),
+            _ => {}
+        },
+        Some(Popup::Input(input)) => match key.code {
+            KeyCode::Esc if matches!(input.action, InputAction::UnlockVault(_)) => app.skip_vault(),
+            KeyCode::Esc => app.popup = None,
+            KeyCode::Enter => app.submit_input(),
+            KeyCode::Backspace => input.pop(),
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        },
+        Some(Popup::Confirm(_)) => match key.code {
+            KeyCode::Char('y') | KeyCode::Enter => app.confirm(),
+            KeyCode::Char('n') => app.decline(),
+            KeyCode::Esc => app.popup = None,
+            _ => {}
+        },
+        None => {}
+    }
+}
+
+fn handle_tunnels_key(app: &mut App, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Char('T') | KeyCode::Esc => app.view = View::Servers,
+        KeyCode::Char('j') | KeyCode::Down => app.tunnels.state.select_next(),
+        KeyCode::Char('k') | KeyCode::Up => app.tunnels.state.select_previous(),
+        KeyCode::Char('s') | KeyCode::Char('d') => app.stop_selected_tunnel(),
+        KeyCode::Char('r') => app.restart_selected_tunnel(),
+        _ => {}
+    }
+}
+
+fn handle_keys_key(app: &mut App, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Char('i') | KeyCode::Esc => app.view = View::Servers,
+        KeyCode::Char('j') | KeyCode::Down => app.keys.state.select_next(),
+        KeyCode::Char('k') | KeyCode::Up => app.keys.state.select_previous(),
+        KeyCode::Char('n') => app.new_key(),
+        KeyCode::Char('A') => app.add_listed_key(),
+        _ => {}
+    }
+}
+
+fn handle_known_hosts_key(app: &mut App, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Char('H') | KeyCode::Esc => app.view = View::Servers,
+        KeyCode::Char('j') | KeyCode::Down => app.unmatched_hosts.state.select_next(),
+        KeyCode::Char('k') | KeyCode::Up => app.unmatched_hosts.state.select_previous(),
+        KeyCode::Char('d') => app.remove_selected_known_host(),
         _ => {}
     }
 }
 
 pub fn handle_mouse(app: &mut App, mouse: MouseEvent) {
+    // A drag ends when the button is released, wherever that is and whatever is shown
+    let drag_from = match mouse.kind {
+        MouseEventKind::Up(_) => app.drag_from.take(),
+        _ => app.drag_from,
+    };
+    if app.popup.is_some() {
+        app.drag_from = None;
+        return;
+    }
+
+    if app.view == View::Tunnels {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => app.tunnels.state.select_previous(),
+            MouseEventKind::ScrollDown => app.tunnels.state.select_next(),
+            _ => {}
+        }
+        return;
+    }
+
+    if app.view == View::Keys {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => app.keys.state.select_previous(),
+            MouseEventKind::ScrollDown => app.keys.state.select_next(),
+            _ => {}
+        }
+        return;
+    }
+
+    if app.view == View::KnownHosts {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => app.unmatched_hosts.state.select_previous(),
+            MouseEventKind::ScrollDown => app.unmatched_hosts.state.select_next(),
+            _ => {}
+        }
+        return;
+    }
+
+    // Clicks, drags and drops only count on the rows of the list, not on the panels around it
+    let row = list_row_at(app, &mouse);
     match mouse.kind {
         MouseEventKind::Down(_) => {
-            // Calculates the list item index corresponding to the clicked location
-            if let Some(selected_index) = app.server_list.get_index_at_y(mouse.row as usize) {
+            if let Some(selected_index) = row {
                 app.server_list.state.select(Some(selected_index));
+                app.drag_from = Some(selected_index);
 
                 // Check for double click
                 if let Some(last_click) = app.last_click_time {
                     if last_click.elapsed() < Duration::from_millis(300) {
                         // Double click detected, toggle group or perform login
-                        if let Some(server) = app.server_list.selected() {
-                            if server.is_group {
-                                app.server_list.toggle_group();
-                            } else {
-                                app.should_exit = true;
-                                app.has_selected = true;
-                            }
-                        }
+                        app.login_selected();
                     }
                 }
                 app.last_click_time = Some(std::time::Instant::now());
             }
         }
+        // Hosts dragged onto another row move after it
+        MouseEventKind::Drag(_) => {
+            if let Some(index) = row {
+                app.server_list.state.select(Some(index));
+            }
+        }
+        MouseEventKind::Up(_) => {
+            if let (Some(from), Some(to)) = (drag_from, row) {
+                app.drop_host(from, to);
+            }
+        }
         MouseEventKind::ScrollUp => {
             app.server_list.select_previous();
         }
@@ -110,3 +242,13 @@ pub fn handle_mouse(app: &mut App, mouse: MouseEvent) {
         _ => {}
     }
 }
+
+/// The visible row of the server list under the mouse, `None` outside the list.
+fn list_row_at(app: &App, mouse: &MouseEvent) -> Option<usize> {
+    let area = app.list_area;
+    if !area.contains(Position::new(mouse.column, mouse.row)) {
+        return None;
+    }
+    app.server_list
+        .get_index_at_y(usize::from(mouse.row - area.y))
+}
@@ -0,0 +1,113 @@
+use crate::deploy::Deployment;
+use crate::keys::KeyResult;
+use crate::probe::ProbeResult;
+use crossterm::event::{self, Event};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// What the run loop reacts to.
+#[derive(Debug)]
+pub enum AppEvent {
+    Input(Event),
+    /// Sent at a fixed interval to check on background work such as tunnels.
+    Tick,
+    Probe(ProbeResult),
+    Deployed(Box<Deployment>),
+    KeyDone(KeyResult),
+}
+
+impl From<ProbeResult> for AppEvent {
+    fn from(result: ProbeResult) -> Self {
+        AppEvent::Probe(result)
+    }
+}
+
+impl From<KeyResult> for AppEvent {
+    fn from(result: KeyResult) -> Self {
+        AppEvent::KeyDone(result)
+    }
+}
+
+impl From<Deployment> for AppEvent {
+    fn from(deployment: Deployment) -> Self {
+        AppEvent::Deployed(Box::new(deployment))
+    }
+}
+
+/// Terminal input, ticks and the results of background workers, in the order they happen.
+#[derive(Debug)]
+pub struct Events {
+    tx: Sender<AppEvent>,
+    rx: Receiver<AppEvent>,
+    stop: Arc<AtomicBool>,
+    input: Option<JoinHandle<()>>,
+}
+
+impl Events {
+    /// Starts reading the terminal on a thread of its own, with a tick every `tick`.
+    pub fn new(tick: Duration) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let input = {
+            let tx = tx.clone();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut next_tick = Instant::now() + tick;
+                while !stop.load(Ordering::Relaxed) {
+                    // Polled rather than read, so the thread stops before ssh takes the terminal
+                    // instead of swallowing its first key
+                    let timeout = next_tick.saturating_duration_since(Instant::now());
+                    let event = match event::poll(timeout) {
+                        Ok(true) => event::read().ok().map(AppEvent::Input),
+                        Ok(false) => None,
+                        Err(_) => return,
+                    };
+                    if let Some(event) = event {
+                        if tx.send(event).is_err() {
+                            return;
+                        }
+                    }
+                    if Instant::now() >= next_tick {
+                        next_tick = Instant::now() + tick;
+                        if tx.send(AppEvent::Tick).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+        };
+        Self {
+            tx,
+            rx,
+            stop,
+            input: Some(input),
+        }
+    }
+
+    /// A sender for background workers to report through.
+    pub fn sender(&self) -> Sender<AppEvent> {
+        self.tx.clone()
+    }
+
+    /// Waits for the next event.
+    pub fn next(&self) -> Option<AppEvent> {
+        self.rx.recv().ok()
+    }
+
+    /// The next event if one is already waiting.
+    pub fn try_next(&self) -> Option<AppEvent> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(input) = self.input.take() {
+            let _ = input.join();
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::backup::backup;
+use crate::config_file::write_atomic;
+use crate::vault::Vault;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// What a write did to one config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    /// `None` if the write created the file.
+    pub before: Option<String>,
+    pub after: String,
+}
+
+/// What a write did to the password the vault holds for one host, at most one per host and edit.
+#[derive(Clone, PartialEq)]
+pub struct SecretChange {
+    pub host: String,
+    /// `None` if the vault had no password for the host.
+    pub before: Option<Zeroizing<String>>,
+    pub after: Option<Zeroizing<String>>,
+}
+
+/// An edit made from the TUI, one or several files and vault passwords changed together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub description: String,
+    pub changes: Vec<FileChange>,
+    pub secrets: Vec<SecretChange>,
+}
+
+/// The edits of the session that can be undone and redone.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    pub fn record(
+        &mut self,
+        description: String,
+        changes: Vec<FileChange>,
+        secrets: Vec<SecretChange>,
+    ) {
+        if changes.is_empty() && secrets.is_empty() {
+            return;
+        }
+        self.undo.push(Edit {
+            description,
+            changes,
+            secrets,
+        });
+        self.redo.clear();
+    }
+
+    /// Puts the files and the vault back as they were before the last edit, returning it.
+    pub fn undo(
+        &mut self,
+        backup_dir: Option<&Path>,
+        vault: Option<&mut Vault>,
+    ) -> io::Result<Option<&Edit>> {
+        let Some(edit) = self.undo.pop() else {
+            return Ok(None);
+        };
+        if let Err(e) = apply(&edit, true, backup_dir, vault) {
+            self.undo.push(edit);
+            return Err(e);
+        }
+        self.redo.push(edit);
+        Ok(self.redo.last())
+    }
+
+    /// Makes the last undone edit again, returning it.
+    pub fn redo(
+        &mut self,
+        backup_dir: Option<&Path>,
+        vault: Option<&mut Vault>,
+    ) -> io::Result<Option<&Edit>> {
+        let Some(edit) = self.redo.pop() else {
+            return Ok(None);
+        };
+        if let Err(e) = apply(&edit, false, backup_dir, vault) {
+            self.redo.push(edit);
+            return Err(e);
+        }
+        self.undo.push(edit);
+        Ok(self.undo.last())
+    }
+}
+
+impl FileChange {
+    /// The content the file should have and the one to write, `None` meaning no file.
+    fn contents(&self, undo: bool) -> (Option<&str>, Option<&str>) {
+        if undo {
+            (Some(&self.after), self.before.as_deref())
+        } else {
+            (self.before.as_deref(), Some(&self.after))
+        }
+    }
+}
+
+impl SecretChange {
+    /// Setting the password `vault` holds for `host` to `after`, `None` removing it.
+    pub fn new(vault: &Vault, host: &str, after: Option<&str>) -> Self {
+        let secret = |secret: &str| Zeroizing::new(secret.to_string());
+        Self {
+            host: host.to_string(),
+            before: vault.get(host).map(secret),
+            after: after.map(secret),
+        }
+    }
+
+    /// Sets the password the change leads to, or the one it replaced if `undo`.
+    pub fn apply(&self, vault: &mut Vault, undo: bool) {
+        match self.secrets(undo).1 {
+            Some(secret) => vault.set(&self.host, secret),
+            None => vault.remove(&self.host),
+        }
+    }
+
+    /// The password the vault should have for the host and the one to set, `None` meaning none.
+    fn secrets(&self, undo: bool) -> (Option<&str>, Option<&str>) {
+        let before = self.before.as_ref().map(|secret| secret.as_str());
+        let after = self.after.as_ref().map(|secret| secret.as_str());
+        if undo {
+            (after, before)
+        } else {
+            (before, after)
+        }
+    }
+}
+
+// Keeps the passwords out of debug output
+impl fmt::Debug for SecretChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretChange")
+            .field("host", &self.host)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reverts `edit` or makes it again. Nothing is written if a file or a password was changed
+/// outside lazyssh in the meantime, or if the vault it changed is locked.
+fn apply(
+    edit: &Edit,
+    undo: bool,
+    backup_dir: Option<&Path>,
+    vault: Option<&mut Vault>,
+) -> io::Result<()> {
+    let changes = &edit.changes;
+    let vault = match vault {
+        Some(vault) => Some(vault),
+        None if edit.secrets.is_empty() => None,
+        None => return Err(io::Error::other("the vault is locked")),
+    };
+    if let Some(vault) = &vault {
+        for secret in &edit.secrets {
+            if vault.get(&secret.host) != secret.secrets(undo).0 {
+                return Err(io::Error::other(format!(
+                    "the password of {} was changed since",
+                    secret.host
+                )));
+            }
+        }
+    }
+    for change in changes {
+        let current = match fs::read_to_string(&change.path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        if current.as_deref() != change.contents(undo).0 {
+            return Err(io::Error::other(format!(
+                "{} was changed outside lazyssh",
+                change.path.display()
+            )));
+        }
+    }
+    for change in changes {
+        if let Some(dir) = backup_dir {
+            backup(&change.path, dir)?;
+        }
+        match change.contents(undo).1 {
+            Some(content) => write_atomic(&change.path, content)?,
+            None => fs::remove_file(&change.path)?,
+        }
+    }
+    let Some(vault) = vault else {
+        return Ok(());
+    };
+    for secret in &edit.secrets {
+        secret.apply(vault, undo);
+    }
+    vault.save()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::history::{FileChange, History};
+    use crate::test_util::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn test_undo_redo() {
+        let dir = temp_dir("history");
+        let config = dir.join("config");
+        let included = dir.join("included");
+        fs::write(&config, "Host a\n").unwrap();
+
+        let mut history = History::default();
+        assert!(history.undo(None, None).unwrap().is_none());
+        fs::write(&config, "Host b\n").unwrap();
+        fs::write(&included, "Host c\n").unwrap();
+        history.record(
+            "add b".to_string(),
+            vec![
+                FileChange {
+                    path: config.clone(),
+                    before: Some("Host a\n".to_string()),
+                    after: "Host b\n".to_string(),
+                },
+                FileChange {
+                    path: included.clone(),
+                    before: None,
+                    after: "Host c\n".to_string(),
+                },
+            ],
+            Vec::new(),
+        );
+
+        let edit = history.undo(None, None).unwrap().unwrap();
+        assert_eq!(edit.description, "add b");
+        assert_eq!(fs::read_to_string(&config).unwrap(), "Host a\n");
+        assert!(!included.exists());
+
+        history.redo(None, None).unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&config).unwrap(), "Host b\n");
+        assert_eq!(fs::read_to_string(&included).unwrap(), "Host c\n");
+
+        // Changes made by hand since aren't overwritten
+        fs::write(&config, "Host d\n").unwrap();
+        assert!(history.undo(None, None).is_err());
+        assert_eq!(fs::read_to_string(&config).unwrap(), "Host d\n");
+        assert_eq!(fs::read_to_string(&included).unwrap(), "Host c\n");
+    }
+}
@@ -0,0 +1,579 @@
+use crate::agent;
+use crate::askpass::Askpass;
+use crate::popup::{AddKey, NewKey};
+use crate::server::ServerItem;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use ratatui::widgets::TableState;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+const OPENSSH_MAGIC: &[u8] = b"openssh-key-v1\0";
+// Keys are kept in subdirectories like ~/.ssh/keys, but not buried deeper
+const SCAN_DEPTH: usize = 3;
+// Key files are a few KB at most, anything larger isn't one
+const MAX_KEY_LEN: u64 = 64 * 1024;
+// ssh tries these when the config has no IdentityFile and skips the ones that don't exist
+const DEFAULT_IDENTITIES: [&str; 7] = [
+    "id_rsa",
+    "id_ecdsa",
+    "id_ecdsa_sk",
+    "id_ed25519",
+    "id_ed25519_sk",
+    "id_xmss",
+    "id_dsa",
+];
+pub const KEY_TYPES: [&str; 3] = ["ed25519", "ecdsa", "rsa"];
+
+/// A key pair found in `~/.ssh` or named by an `IdentityFile`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyInfo {
+    /// The private key, the path of the `.pub` file without its extension if there is none.
+    pub path: PathBuf,
+    pub has_private: bool,
+    pub has_public: bool,
+    /// The public key in the SSH wire format, `None` if it couldn't be read.
+    pub blob: Option<Vec<u8>>,
+    pub comment: String,
+    /// Whether the private key is protected by a passphrase, `None` without a private key.
+    pub encrypted: Option<bool>,
+    /// The hosts whose `IdentityFile` is this key.
+    pub hosts: Vec<String>,
+}
+
+/// The keys shown in the keys view.
+#[derive(Debug, Default)]
+pub struct KeyInventory {
+    pub keys: Vec<KeyInfo>,
+    pub state: TableState,
+}
+
+/// Reads the SSH wire format, made of big-endian integers and length-prefixed strings.
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated SSH data",
+            ));
+        }
+        let (data, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn string(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+/// `path` with the home directory shortened to `~`.
+pub fn display_path(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(PathBuf::from)) {
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// The path an `IdentityFile` value points to, with `~` expanded.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+/// The public key of the key pair at `path`, as the agent reports it: from the `.pub` file
+/// next to it or, failing that, from the header of an OpenSSH private key, which isn't
+/// encrypted.
+pub fn public_key_blob(path: &Path) -> io::Result<Vec<u8>> {
+    if let Some((blob, _)) = read_public_key(path) {
+        return Ok(blob);
+    }
+    read_private_key(&fs::read_to_string(path)?)?
+        .blob
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an OpenSSH private key, and no .pub file next to it",
+            )
+        })
+}
+
+/// The `.pub` path of the key pair at `path`.
+pub fn public_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.pub", path.display()))
+}
+
+/// The public key and comment of the `.pub` file of the key pair at `path`.
+fn read_public_key(path: &Path) -> Option<(Vec<u8>, String)> {
+    let content = fs::read_to_string(public_path(path)).ok()?;
+    let mut fields = content.split_whitespace();
+    let blob = STANDARD.decode(fields.nth(1)?).ok()?;
+    let comment: Vec<&str> = fields.collect();
+    Some((blob, comment.join(" ")))
+}
+
+/// The line of the `.pub` file of the key pair at `path`, as it goes in `authorized_keys`.
+pub fn public_key_line(path: &Path) -> io::Result<String> {
+    let public = public_path(path);
+    if read_public_key(path).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a public key", public.display()),
+        ));
+    }
+    let content = fs::read_to_string(public)?;
+    Ok(content
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
+/// What can be read from a private key without its passphrase.
+#[derive(Debug, Default, PartialEq)]
+struct PrivateKey {
+    encrypted: bool,
+    /// Only OpenSSH private keys carry their public key in the clear.
+    blob: Option<Vec<u8>>,
+    /// Stored with the private fields, so only known for keys that aren't encrypted.
+    comment: Option<String>,
+}
+
+/// Reads a private key in the OpenSSH format or one of the PEM formats.
+fn read_private_key(content: &str) -> io::Result<PrivateKey> {
+    let begin = content.lines().next().unwrap_or_default();
+    if !begin.starts_with("-----BEGIN ") || !begin.contains("PRIVATE KEY") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a private key",
+        ));
+    }
+    if !begin.contains("OPENSSH") {
+        // PKCS#1 keys have a `Proc-Type: 4,ENCRYPTED` header, PKCS#8 ones a different label
+        return Ok(PrivateKey {
+            encrypted: content.contains("ENCRYPTED"),
+            ..PrivateKey::default()
+        });
+    }
+
+    let data: String = content
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let data = STANDARD
+        .decode(data.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let Some(data) = data.strip_prefix(OPENSSH_MAGIC) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an OpenSSH private key",
+        ));
+    };
+    let mut reader = Reader::new(data);
+    let encrypted = reader.string()? != b"none";
+    let _kdf = reader.string()?;
+    let _kdf_options = reader.string()?;
+    let _keys = reader.u32()?;
+    let blob = reader.string()?.to_vec();
+    let comment = if encrypted {
+        None
+    } else {
+        private_comment(Reader::new(reader.string()?))
+    };
+    Ok(PrivateKey {
+        encrypted,
+        blob: Some(blob),
+        comment,
+    })
+}
+
+/// The comment following the private fields of an unencrypted key.
+fn private_comment(mut reader: Reader) -> Option<String> {
+    let _check = reader.u32().ok()?;
+    let _check = reader.u32().ok()?;
+    let fields = match reader.string().ok()? {
+        b"ssh-ed25519" => 2,
+        b"ssh-rsa" => 6,
+        b"ssh-dss" => 5,
+        key_type if key_type.starts_with(b"ecdsa-sha2-") => 3,
+        _ => return None,
+    };
+    for _ in 0..fields {
+        reader.string().ok()?;
+    }
+    Some(String::from_utf8_lossy(reader.string().ok()?).to_string())
+}
+
+/// The length in bits of an SSH `mpint`.
+fn mpint_bits(mpint: &[u8]) -> u32 {
+    match mpint.iter().position(|&byte| byte != 0) {
+        Some(i) => (mpint.len() - i) as u32 * 8 - mpint[i].leading_zeros(),
+        None => 0,
+    }
+}
+
+/// The type and size of the public key `blob`, named like `ssh-keygen -l` does.
+pub fn key_type(blob: &[u8]) -> Option<(&'static str, u32)> {
+    let mut reader = Reader::new(blob);
+    Some(match reader.string().ok()? {
+        b"ssh-ed25519" => ("ED25519", 256),
+        b"sk-ssh-ed25519@openssh.com" => ("ED25519-SK", 256),
+        b"ssh-rsa" => {
+            let _exponent = reader.string().ok()?;
+            ("RSA", mpint_bits(reader.string().ok()?))
+        }
+        b"ssh-dss" => ("DSA", mpint_bits(reader.string().ok()?)),
+        b"ecdsa-sha2-nistp256" => ("ECDSA", 256),
+        b"ecdsa-sha2-nistp384" => ("ECDSA", 384),
+        b"ecdsa-sha2-nistp521" => ("ECDSA", 521),
+        b"sk-ecdsa-sha2-nistp256@openssh.com" => ("ECDSA-SK", 256),
+        _ => return None,
+    })
+}
+
+/// The SHA256 fingerprint of the public key `blob`, as `ssh-keygen -l` prints it.
+pub fn fingerprint(blob: &[u8]) -> String {
+    format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(blob)))
+}
+
+/// Reads the key pair at `path`, either half of which may be missing.
+fn read_key(path: &Path) -> KeyInfo {
+    let private = fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file() && metadata.len() <= MAX_KEY_LEN)
+        .and_then(|_| fs::read_to_string(path).ok())
+        .and_then(|content| read_private_key(&content).ok());
+    let public = read_public_key(path);
+    let has_public = public.is_some() || public_path(path).exists();
+    let (blob, comment) = match (public, &private) {
+        (Some((blob, comment)), _) => (Some(blob), comment),
+        (None, Some(private)) => (
+            private.blob.clone(),
+            private.comment.clone().unwrap_or_default(),
+        ),
+        (None, None) => (None, String::new()),
+    };
+    KeyInfo {
+        path: path.to_path_buf(),
+        has_private: private.is_some(),
+        has_public,
+        blob,
+        comment,
+        encrypted: private.map(|private| private.encrypted),
+        hosts: Vec::new(),
+    }
+}
+
+/// Adds the key pairs under `dir` to `found`, keyed by the path of their private key.
+fn find_keys(dir: &Path, depth: usize, found: &mut BTreeMap<PathBuf, KeyInfo>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if depth > 0 {
+                find_keys(&path, depth - 1, found);
+            }
+            continue;
+        }
+        let path = match path.to_str().and_then(|path| path.strip_suffix(".pub")) {
+            Some(private) => PathBuf::from(private),
+            None => path,
+        };
+        if found.contains_key(&path) {
+            continue;
+        }
+        let key = read_key(&path);
+        if key.has_private || key.blob.is_some() {
+            found.insert(path, key);
+        }
+    }
+}
+
+/// The key pairs under `dir` and the ones the `IdentityFile`s of `servers` name, with the hosts
+/// using each of them.
+pub fn scan(dir: &Path, servers: &[ServerItem]) -> Vec<KeyInfo> {
+    let mut found = BTreeMap::new();
+    find_keys(dir, SCAN_DEPTH, &mut found);
+    for server in servers.iter().filter(|server| !server.is_group) {
+        for identity_file in &server.identity_files {
+            let path = expand_home(identity_file);
+            let key = found.entry(path.clone()).or_insert_with(|| read_key(&path));
+            if !key.hosts.contains(&server.host) {
+                key.hosts.push(server.host.clone());
+            }
+        }
+    }
+    // Default identities that don't exist are skipped by ssh rather than missing
+    found
+        .into_values()
+        .filter(|key| !key.is_missing() || !is_default_identity(dir, &key.path))
+        .collect()
+}
+
+fn is_default_identity(dir: &Path, path: &Path) -> bool {
+    DEFAULT_IDENTITIES.iter().any(|name| dir.join(name) == path)
+}
+
+/// Creates a key pair of type `key_type` at `path` with `ssh-keygen`, protected by
+/// `passphrase` unless it is empty. The comment is ssh-keygen's default, `user@hostname`.
+pub fn generate_key(key_type: &str, path: &Path, passphrase: &str) -> io::Result<()> {
+    if path.exists() || public_path(path).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.exists()) {
+        fs::create_dir_all(parent)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(parent, fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    let mut command = Command::new("ssh-keygen");
+    command.args(["-q", "-t", key_type, "-f"]).arg(path);
+    // Asked twice, the second time to confirm it. Kept out of `-N`, where other users could see it
+    let _askpass = if passphrase.is_empty() {
+        command.args(["-N", ""]);
+        None
+    } else {
+        let askpass = Askpass::serve_times(passphrase, "passphrase", 2)?;
+        askpass.configure(&mut command, false)?;
+        Some(askpass)
+    };
+    let output = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::other("ssh-keygen is not installed"),
+            _ => e,
+        })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = stderr
+        .lines()
+        .rfind(|line| !line.trim().is_empty())
+        .unwrap_or("ssh-keygen failed");
+    Err(io::Error::other(error.to_string()))
+}
+
+/// What a `KeyJob` does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyTask {
+    Generate(NewKey),
+    Add(AddKey),
+}
+
+/// Runs `ssh-keygen` or `ssh-add` on a worker, they wait for their passphrase prompts to be
+/// answered through askpass.
+#[derive(Clone, PartialEq)]
+pub struct KeyJob {
+    pub task: KeyTask,
+    pub passphrase: Zeroizing<String>,
+}
+
+/// The outcome of a `KeyJob`.
+#[derive(Debug)]
+pub struct KeyResult {
+    pub task: KeyTask,
+    pub result: io::Result<()>,
+}
+
+impl KeyJob {
+    pub fn run(self) -> KeyResult {
+        let result = match &self.task {
+            KeyTask::Generate(new_key) => generate_key(
+                &new_key.key_type,
+                &expand_home(&new_key.path),
+                &self.passphrase,
+            ),
+            KeyTask::Add(add_key) => agent::add_key(
+                &expand_home(&add_key.key),
+                &self.passphrase,
+                add_key.lifetime.as_deref(),
+            ),
+        };
+        KeyResult {
+            task: self.task,
+            result,
+        }
+    }
+}
+
+// Keeps the passphrase out of debug output
+impl fmt::Debug for KeyJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyJob")
+            .field("task", &self.task)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyInfo {
+    /// Whether no host uses the key.
+    pub fn is_orphan(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Whether an `IdentityFile` names a key that doesn't exist.
+    pub fn is_missing(&self) -> bool {
+        !self.has_private && !self.has_public
+    }
+
+    /// The path with the home directory shortened to `~`, as written in the config.
+    pub fn display_path(&self) -> String {
+        display_path(&self.path)
+    }
+}
+
+impl KeyInventory {
+    /// Scans `~/.ssh` again, keeping the selection on the same key.
+    pub fn refresh(&mut self, servers: &[ServerItem]) {
+        let selected = self.selected().map(|key| key.path.clone());
+        let dir = dirs::home_dir().unwrap_or_default().join(".ssh");
+        self.keys = scan(&dir, servers);
+        match selected {
+            Some(path) => self.select(&path),
+            None => self.state.select((!self.keys.is_empty()).then_some(0)),
+        }
+    }
+
+    pub fn selected(&self) -> Option<&KeyInfo> {
+        self.state.selected().and_then(|i| self.keys.get(i))
+    }
+
+    pub fn select(&mut self, path: &Path) {
+        let index = self.keys.iter().position(|key| key.path == path);
+        self.state
+            .select(index.or((!self.keys.is_empty()).then_some(0)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::keys::{fingerprint, key_type, public_key_blob, scan};
+    use crate::server::ServerList;
+    use crate::test_util::{fixture, temp_dir};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use std::fs;
+
+    #[test]
+    fn test_public_key_blob() {
+        let public = fs::read_to_string(fixture("keys/plain.pub")).unwrap();
+        let expected = STANDARD
+            .decode(public.split_whitespace().nth(1).unwrap())
+            .unwrap();
+        assert_eq!(public_key_blob(&fixture("keys/plain")).unwrap(), expected);
+
+        // Without a .pub file, read from the private key, even an encrypted one
+        let blob = public_key_blob(&fixture("keys/nopub")).unwrap();
+        assert!(blob.starts_with(b"\0\0\0\x0bssh-ed25519"));
+        let public = fs::read_to_string(fixture("keys/encrypted.pub")).unwrap();
+        let copy = temp_dir("key").join("encrypted");
+        fs::copy(fixture("keys/encrypted"), &copy).unwrap();
+        let blob = public_key_blob(&copy).unwrap();
+        assert_eq!(
+            STANDARD.encode(blob),
+            public.split_whitespace().nth(1).unwrap()
+        );
+
+        assert!(public_key_blob(&fixture("keys/missing")).is_err());
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = fixture("keys");
+        let config = temp_dir("keys").join("config");
+        fs::write(
+            &config,
+            format!(
+                "Host web\n    IdentityFile {dir}/plain\n\n\
+                 Host db\n    IdentityFile {dir}/plain\n    IdentityFile {dir}/gone\n\n\
+                 Host ci\n    IdentityFile {dir}/rsa\n    IdentityFile {dir}/id_ed25519\n",
+                dir = dir.display()
+            ),
+        )
+        .unwrap();
+        let list = ServerList::from_config_file(&config);
+        let keys = scan(&dir, &list.items);
+        let _ = fs::remove_file(&config);
+        let key = |name: &str| keys.iter().find(|key| key.path == dir.join(name));
+
+        let names: Vec<String> = keys
+            .iter()
+            .map(|key| key.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        // A default identity that doesn't exist isn't missing, ssh skips it
+        assert_eq!(
+            names,
+            ["encrypted", "gone", "lonely", "nopub", "plain", "rsa"]
+        );
+
+        let plain = key("plain").unwrap();
+        assert_eq!(plain.hosts, ["web", "db"]);
+        assert_eq!(plain.comment, "plain@lazyssh");
+        assert_eq!(plain.encrypted, Some(false));
+        let blob = plain.blob.as_deref().unwrap();
+        assert_eq!(key_type(blob), Some(("ED25519", 256)));
+        assert_eq!(
+            fingerprint(blob),
+            "SHA256:Hw1RGdv1skDaTNTkiknKFsltG9ngnInP5UR3WDk80sE"
+        );
+
+        let rsa = key("rsa").unwrap();
+        assert_eq!(key_type(rsa.blob.as_deref().unwrap()), Some(("RSA", 2048)));
+        assert_eq!(rsa.hosts, ["ci"]);
+
+        let encrypted = key("encrypted").unwrap();
+        assert_eq!(encrypted.encrypted, Some(true));
+        assert!(encrypted.is_orphan());
+
+        // The comment of an unencrypted key without .pub is read from the private key
+        let nopub = key("nopub").unwrap();
+        assert!(nopub.has_private && !nopub.has_public);
+        assert_eq!(nopub.comment, "nopub@lazyssh");
+
+        let lonely = key("lonely").unwrap();
+        assert!(!lonely.has_private && lonely.has_public);
+        assert_eq!(lonely.encrypted, None);
+
+        let gone = key("gone").unwrap();
+        assert!(gone.is_missing());
+        assert_eq!(gone.hosts, ["db"]);
+    }
+}
@@ -0,0 +1,653 @@
+use crate::backup::backup;
+use crate::config_file::write_atomic;
+use crate::history::FileChange;
+use crate::keys::{fingerprint, key_type};
+use crate::server::{match_comma_list, ServerItem};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use hmac::{Hmac, Mac};
+use ratatui::widgets::TableState;
+use sha1::Sha1;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const DEFAULT_PORT: u16 = 22;
+// ssh-keyscan gives up on a host after this many seconds
+const KEYSCAN_TIMEOUT: &str = "5";
+// Length of the salt ssh hashes host names with
+const SALT_LEN: usize = 20;
+
+/// A key a host presents, as its `type` and blob.
+pub type HostKey = (String, Vec<u8>);
+
+/// `@cert-authority` or `@revoked` in front of a known_hosts line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Marker {
+    CertAuthority,
+    Revoked,
+}
+
+/// The host names a known_hosts line applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostPatterns {
+    /// Comma-separated patterns such as `web,10.0.0.1` or `[db]:2222`.
+    Plain(String),
+    /// `|1|salt|hash`, the HMAC-SHA1 of the name keyed with the salt.
+    Hashed { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+/// A key pinned for some hosts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownHost {
+    pub path: PathBuf,
+    /// 1-based.
+    pub line: usize,
+    pub marker: Option<Marker>,
+    pub hosts: HostPatterns,
+    pub key_type: String,
+    pub blob: Vec<u8>,
+}
+
+/// The keys of the known_hosts files.
+#[derive(Debug, Default)]
+pub struct KnownHosts {
+    pub entries: Vec<KnownHost>,
+}
+
+/// The keys of the user's known_hosts files that no configured host is looked up by.
+#[derive(Debug, Default)]
+pub struct UnmatchedHosts {
+    pub entries: Vec<KnownHost>,
+    pub state: TableState,
+}
+
+/// What the known_hosts files say about a host before connecting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KnownStatus {
+    /// Keys of these types are pinned for the host.
+    Known(Vec<String>),
+    /// Only a `@cert-authority` line applies, the host's certificate is checked against it.
+    CertAuthority,
+    /// ssh will ask whether to trust the key it is shown.
+    Unknown,
+}
+
+/// How the keys a host presents compare to the pinned ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostKeyCheck {
+    Match,
+    /// No key of a type the host presents is pinned.
+    Unknown,
+    CertAuthority,
+    /// The host presents a `key_type` key other than the pinned one, ssh will refuse to connect.
+    Mismatch {
+        key_type: String,
+        known: String,
+        live: String,
+        path: PathBuf,
+        line: usize,
+    },
+    /// The host presents a key marked `@revoked`.
+    Revoked {
+        fingerprint: String,
+    },
+}
+
+/// The name ssh looks a host up by: its address, with the port if it isn't 22.
+pub fn host_name(address: &str, port: u16) -> String {
+    if port == DEFAULT_PORT {
+        address.to_string()
+    } else {
+        format!("[{}]:{}", address, port)
+    }
+}
+
+/// The user's known_hosts files, the ones lazyssh edits. New keys go to the first one.
+pub fn user_known_hosts_paths() -> Vec<PathBuf> {
+    match dirs::home_dir() {
+        Some(home) => vec![
+            home.join(".ssh/known_hosts"),
+            home.join(".ssh/known_hosts2"),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// The known_hosts files ssh reads by default, the user's and the system-wide ones.
+pub fn known_hosts_paths() -> Vec<PathBuf> {
+    let mut paths = user_known_hosts_paths();
+    paths.push(PathBuf::from("/etc/ssh/ssh_known_hosts"));
+    paths.push(PathBuf::from("/etc/ssh/ssh_known_hosts2"));
+    paths
+}
+
+/// The names keys of `server` may be pinned under: its alias, its `HostName` and the
+/// `addresses` it resolves to, each alone and as `[name]:port`, since the port may have changed.
+pub fn pinned_names(server: &ServerItem, addresses: &[IpAddr]) -> Vec<String> {
+    let port = u16::try_from(server.port).unwrap_or(DEFAULT_PORT);
+    let mut names: Vec<String> = Vec::new();
+    let addresses = addresses.iter().map(IpAddr::to_string);
+    for name in [server.host.clone(), server.hostname().to_string()]
+        .into_iter()
+        .chain(addresses)
+    {
+        let name = name.to_lowercase();
+        for name in [host_name(&name, port), name.clone()] {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// The IP addresses `address` resolves to, empty if it doesn't.
+pub fn resolve(address: &str) -> Vec<IpAddr> {
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    for addr in (address, 0).to_socket_addrs().into_iter().flatten() {
+        if !addresses.contains(&addr.ip()) {
+            addresses.push(addr.ip());
+        }
+    }
+    addresses
+}
+
+fn parse_hashed(patterns: &str) -> Option<HostPatterns> {
+    let mut parts = patterns.strip_prefix("|1|")?.split('|');
+    let salt = STANDARD.decode(parts.next()?).ok()?;
+    let hash = STANDARD.decode(parts.next()?).ok()?;
+    Some(HostPatterns::Hashed { salt, hash })
+}
+
+/// Parses a known_hosts line, `None` for comments and lines ssh would ignore.
+fn parse_line(path: &Path, line: usize, content: &str) -> Option<KnownHost> {
+    let mut fields = content.split_whitespace().peekable();
+    let marker = match fields.peek()? {
+        marker if marker.starts_with('#') => return None,
+        &"@cert-authority" => Some(Marker::CertAuthority),
+        &"@revoked" => Some(Marker::Revoked),
+        marker if marker.starts_with('@') => return None,
+        _ => None,
+    };
+    if marker.is_some() {
+        fields.next();
+    }
+    let patterns = fields.next()?;
+    let hosts = if patterns.starts_with("|1|") {
+        parse_hashed(patterns)?
+    } else {
+        HostPatterns::Plain(patterns.to_string())
+    };
+    let key_type = fields.next()?.to_string();
+    let blob = STANDARD.decode(fields.next()?).ok()?;
+    Some(KnownHost {
+        path: path.to_path_buf(),
+        line,
+        marker,
+        hosts,
+        key_type,
+        blob,
+    })
+}
+
+/// The `type base64` keys of ssh-keyscan's output.
+fn parse_keyscan(output: &str) -> Vec<HostKey> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let key_type = fields.next()?.to_string();
+            let blob = STANDARD.decode(fields.next()?).ok()?;
+            Some((key_type, blob))
+        })
+        .collect()
+}
+
+/// Fetches the host keys the server at `address:port` presents with `ssh-keyscan`.
+pub fn scan_host_keys(address: &str, port: u16) -> io::Result<Vec<HostKey>> {
+    let output = Command::new("ssh-keyscan")
+        .args([
+            "-T",
+            KEYSCAN_TIMEOUT,
+            "-p",
+            &port.to_string(),
+            "--",
+            address,
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::other("ssh-keyscan is not installed"),
+            _ => e,
+        })?;
+    let keys = parse_keyscan(&String::from_utf8_lossy(&output.stdout));
+    if keys.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} presented no host key",
+            host_name(address, port)
+        )));
+    }
+    Ok(keys)
+}
+
+/// Backs up each file that changes before writing it. Nothing is written if one can't be backed
+/// up.
+fn write_changes(changes: &[FileChange], backup_dir: Option<&Path>) -> io::Result<()> {
+    if let Some(dir) = backup_dir {
+        for change in changes {
+            backup(&change.path, dir)?;
+        }
+    }
+    for change in changes {
+        write_atomic(&change.path, &change.after)?;
+    }
+    Ok(())
+}
+
+/// Removes the lines of `entries` from their files, returning what changed. Nothing is written if
+/// a file was changed since it was read.
+pub fn remove_entries(
+    entries: &[KnownHost],
+    backup_dir: Option<&Path>,
+) -> io::Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+    let mut paths: Vec<&Path> = Vec::new();
+    for entry in entries {
+        if !paths.contains(&entry.path.as_path()) {
+            paths.push(&entry.path);
+        }
+    }
+    for path in paths {
+        let before = fs::read_to_string(path)?;
+        let lines: Vec<&str> = before.split_inclusive('\n').collect();
+        let mut removed = vec![false; lines.len()];
+        for entry in entries.iter().filter(|entry| entry.path == path) {
+            let current = lines
+                .get(entry.line - 1)
+                .and_then(|content| parse_line(path, entry.line, content));
+            if current.as_ref() != Some(entry) {
+                return Err(io::Error::other(format!(
+                    "{} was changed since it was read",
+                    path.display()
+                )));
+            }
+            removed[entry.line - 1] = true;
+        }
+        let after: String = lines
+            .iter()
+            .zip(removed)
+            .filter(|(_, removed)| !removed)
+            .map(|(line, _)| *line)
+            .collect();
+        changes.push(FileChange {
+            path: path.to_path_buf(),
+            before: Some(before),
+            after,
+        });
+    }
+    write_changes(&changes, backup_dir)?;
+    Ok(changes)
+}
+
+/// `|1|salt|hash` for `name`, as ssh writes it with `HashKnownHosts yes`.
+fn hash_name(name: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut mac = Hmac::<Sha1>::new_from_slice(&salt).expect("HMAC takes keys of any length");
+    mac.update(name.to_lowercase().as_bytes());
+    format!(
+        "|1|{}|{}",
+        STANDARD.encode(salt),
+        STANDARD.encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Appends `keys` for the host looked up as `name` to the known_hosts file `path`, returning what
+/// changed. The name is hashed if the file already holds hashed entries.
+pub fn pin_keys(
+    path: &Path,
+    name: &str,
+    keys: &[HostKey],
+    backup_dir: Option<&Path>,
+) -> io::Result<FileChange> {
+    let before = match fs::read_to_string(path) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let mut after = before.clone().unwrap_or_default();
+    if !after.is_empty() && !after.ends_with('\n') {
+        after.push('\n');
+    }
+    let hashed = after.lines().enumerate().any(|(i, line)| {
+        parse_line(path, i + 1, line)
+            .is_some_and(|entry| matches!(entry.hosts, HostPatterns::Hashed { .. }))
+    });
+    for (key_type, blob) in keys {
+        let hosts = match hashed {
+            true => hash_name(name),
+            false => name.to_lowercase(),
+        };
+        after.push_str(&format!(
+            "{} {} {}\n",
+            hosts,
+            key_type,
+            STANDARD.encode(blob)
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let change = FileChange {
+        path: path.to_path_buf(),
+        before,
+        after,
+    };
+    write_changes(std::slice::from_ref(&change), backup_dir)?;
+    Ok(change)
+}
+
+/// The type of `blob` named like `ssh-keygen -l` does, as `written` if it is unknown.
+pub fn display_type(blob: &[u8], written: &str) -> String {
+    match key_type(blob) {
+        Some((name, _)) => name.to_string(),
+        None => written.to_string(),
+    }
+}
+
+impl KnownHost {
+    /// Whether the line applies to the host looked up as `name`.
+    pub fn matches(&self, name: &str) -> bool {
+        match &self.hosts {
+            HostPatterns::Plain(patterns) => match_comma_list(name, patterns),
+            HostPatterns::Hashed { salt, hash } => {
+                let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(salt) else {
+                    return false;
+                };
+                mac.update(name.to_lowercase().as_bytes());
+                mac.verify_slice(hash).is_ok()
+            }
+        }
+    }
+}
+
+impl KnownHosts {
+    /// Reads the files of `paths` that exist.
+    pub fn load(paths: &[PathBuf]) -> Self {
+        let mut entries = Vec::new();
+        for path in paths {
+            let Ok(content) = fs::read_to_string(path) else {
+                continue;
+            };
+            for (i, line) in content.lines().enumerate() {
+                entries.extend(parse_line(path, i + 1, line));
+            }
+        }
+        Self { entries }
+    }
+
+    /// The keys of the files of `paths` pinned for any of `names`. `@cert-authority` and
+    /// `@revoked` lines aren't included, they don't belong to a single host.
+    pub fn pinned(&self, names: &[String], paths: &[PathBuf]) -> Vec<KnownHost> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.marker.is_none() && paths.contains(&entry.path))
+            .filter(|entry| names.iter().any(|name| entry.matches(name)))
+            .cloned()
+            .collect()
+    }
+
+    /// The keys of the files of `paths` none of `servers` is looked up by, under its names or the
+    /// `addresses` it resolved to.
+    pub fn unmatched(
+        &self,
+        servers: &[ServerItem],
+        addresses: &HashMap<String, Vec<IpAddr>>,
+        paths: &[PathBuf],
+    ) -> Vec<KnownHost> {
+        let names: Vec<String> = servers
+            .iter()
+            .filter(|server| !server.is_group)
+            .flat_map(|server| {
+                let addresses = addresses.get(&server.host).map_or(&[][..], Vec::as_slice);
+                pinned_names(server, addresses)
+            })
+            .collect();
+        self.entries
+            .iter()
+            .filter(|entry| entry.marker.is_none() && paths.contains(&entry.path))
+            .filter(|entry| !names.iter().any(|name| entry.matches(name)))
+            .cloned()
+            .collect()
+    }
+
+    fn matching<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a KnownHost> + 'a {
+        self.entries.iter().filter(move |entry| entry.matches(name))
+    }
+
+    /// Whether keys are pinned for the host looked up as `name`.
+    pub fn status(&self, name: &str) -> KnownStatus {
+        let mut types: Vec<String> = Vec::new();
+        let mut cert_authority = false;
+        for entry in self.matching(name) {
+            match entry.marker {
+                None => {
+                    let key_type = display_type(&entry.blob, &entry.key_type);
+                    if !types.contains(&key_type) {
+                        types.push(key_type);
+                    }
+                }
+                Some(Marker::CertAuthority) => cert_authority = true,
+                Some(Marker::Revoked) => {}
+            }
+        }
+        match (types.is_empty(), cert_authority) {
+            (false, _) => KnownStatus::Known(types),
+            (true, true) => KnownStatus::CertAuthority,
+            (true, false) => KnownStatus::Unknown,
+        }
+    }
+
+    /// Compares the keys the host looked up as `name` presents, as ssh-keyscan reports them, with
+    /// the pinned ones.
+    pub fn check(&self, name: &str, live: &[HostKey]) -> HostKeyCheck {
+        let entries: Vec<&KnownHost> = self.matching(name).collect();
+        for (_, blob) in live {
+            let revoked = entries
+                .iter()
+                .any(|entry| entry.marker == Some(Marker::Revoked) && entry.blob == *blob);
+            if revoked {
+                return HostKeyCheck::Revoked {
+                    fingerprint: fingerprint(blob),
+                };
+            }
+        }
+
+        let pinned: Vec<&&KnownHost> = entries.iter().filter(|e| e.marker.is_none()).collect();
+        if pinned.is_empty() {
+            return match entries
+                .iter()
+                .any(|e| e.marker == Some(Marker::CertAuthority))
+            {
+                true => HostKeyCheck::CertAuthority,
+                false => HostKeyCheck::Unknown,
+            };
+        }
+        // ssh prefers the key types already pinned, one matching key is enough
+        let matched = live
+            .iter()
+            .any(|(_, blob)| pinned.iter().any(|entry| entry.blob == *blob));
+        if matched {
+            return HostKeyCheck::Match;
+        }
+        for (key_type, blob) in live {
+            if let Some(entry) = pinned.iter().find(|entry| entry.key_type == *key_type) {
+                return HostKeyCheck::Mismatch {
+                    key_type: display_type(blob, key_type),
+                    known: fingerprint(&entry.blob),
+                    live: fingerprint(blob),
+                    path: entry.path.clone(),
+                    line: entry.line,
+                };
+            }
+        }
+        HostKeyCheck::Unknown
+    }
+}
+
+impl UnmatchedHosts {
+    /// Lists the unmatched keys of `known_hosts` again, keeping the selection on the same row.
+    pub fn refresh(
+        &mut self,
+        known_hosts: &KnownHosts,
+        servers: &[ServerItem],
+        addresses: &HashMap<String, Vec<IpAddr>>,
+    ) {
+        self.entries = known_hosts.unmatched(servers, addresses, &user_known_hosts_paths());
+        let selected = self.state.selected().unwrap_or(0);
+        self.state
+            .select((!self.entries.is_empty()).then(|| selected.min(self.entries.len() - 1)));
+    }
+
+    pub fn selected(&self) -> Option<&KnownHost> {
+        self.state.selected().and_then(|i| self.entries.get(i))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::keys::public_key_blob;
+    use crate::known_hosts::{
+        host_name, parse_keyscan, pin_keys, pinned_names, remove_entries, HostKeyCheck, KnownHosts,
+        KnownStatus,
+    };
+    use crate::server::ServerList;
+    use crate::test_util::{fixture, temp_dir};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use std::collections::HashMap;
+    use std::fs;
+
+    /// The `type base64` key of a .pub fixture.
+    fn key(name: &str) -> (String, Vec<u8>) {
+        let content = fs::read_to_string(fixture(&format!("keys/{}.pub", name))).unwrap();
+        let mut fields = content.split_whitespace();
+        let key_type = fields.next().unwrap().to_string();
+        (key_type, STANDARD.decode(fields.next().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_known_hosts() {
+        let known_hosts = KnownHosts::load(&[fixture("known_hosts/known_hosts"), fixture("none")]);
+        assert_eq!(known_hosts.entries.len(), 5);
+
+        let ed25519 = || KnownStatus::Known(vec!["ED25519".to_string()]);
+        assert_eq!(known_hosts.status("web"), ed25519());
+        assert_eq!(known_hosts.status("10.0.0.1"), ed25519());
+        assert_eq!(
+            known_hosts.status(&host_name("hashed.example", 22)),
+            ed25519()
+        );
+        assert_eq!(
+            known_hosts.status(&host_name("db", 2222)),
+            KnownStatus::Known(vec!["RSA".to_string()])
+        );
+        // The port is part of the name
+        assert_eq!(known_hosts.status("db"), KnownStatus::Unknown);
+        assert_eq!(known_hosts.status("build.corp"), KnownStatus::CertAuthority);
+
+        assert_eq!(
+            known_hosts.check("web", &[key("plain")]),
+            HostKeyCheck::Match
+        );
+        match known_hosts.check("web", &[key("rsa"), key("encrypted")]) {
+            HostKeyCheck::Mismatch { key_type, line, .. } => {
+                assert_eq!(key_type, "ED25519");
+                assert_eq!(line, 2);
+            }
+            check => panic!("unexpected {:?}", check),
+        }
+        // Only an RSA key is pinned, ssh asks about the ED25519 one
+        assert_eq!(
+            known_hosts.check("[db]:2222", &[key("plain")]),
+            HostKeyCheck::Unknown
+        );
+        // The private key is the only half of that one
+        let revoked = public_key_blob(&fixture("keys/nopub")).unwrap();
+        assert!(matches!(
+            known_hosts.check("anything", &[("ssh-ed25519".to_string(), revoked)]),
+            HostKeyCheck::Revoked { .. }
+        ));
+
+        let output = "# db:2222 SSH-2.0-OpenSSH_9.6\n[db]:2222 ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIIsqt+rs6PBHOQAZGNNZPIldUfIhI8eQ70oHZESabRZ3\n";
+        assert_eq!(parse_keyscan(output), [key("plain")]);
+    }
+
+    #[test]
+    fn test_edit_known_hosts() {
+        let dir = temp_dir("known-hosts");
+        let path = dir.join("known_hosts");
+        let plain = key("plain");
+        // Pinned under the address db resolves to
+        let original = format!(
+            "{}10.0.0.9 {} {}\n",
+            fs::read_to_string(fixture("known_hosts/known_hosts")).unwrap(),
+            plain.0,
+            STANDARD.encode(&plain.1)
+        );
+        fs::write(&path, &original).unwrap();
+        let config = dir.join("config");
+        fs::write(
+            &config,
+            "Host web\n    HostName 10.0.0.1\n\nHost db\n    Port 2222\n",
+        )
+        .unwrap();
+        let list = ServerList::from_config_file(&config);
+        let paths = [path.clone()];
+        let known_hosts = KnownHosts::load(&paths);
+
+        // Only the hashed key isn't for a configured host, markers aren't listed
+        let mut addresses = HashMap::new();
+        let unmatched = known_hosts.unmatched(&list.items, &addresses, &paths);
+        assert_eq!(unmatched.len(), 2);
+        addresses.insert("db".to_string(), vec!["10.0.0.9".parse().unwrap()]);
+        let unmatched = known_hosts.unmatched(&list.items, &addresses, &paths);
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].line, 4);
+
+        let db = list.items.iter().find(|item| item.host == "db").unwrap();
+        let names = pinned_names(db, &["10.0.0.2".parse().unwrap()]);
+        assert_eq!(names, ["[db]:2222", "db", "[10.0.0.2]:2222", "10.0.0.2"]);
+        let pinned = known_hosts.pinned(&names, &paths);
+        assert_eq!(pinned.len(), 1);
+        assert_eq!(pinned[0].line, 3);
+
+        let changes = remove_entries(&pinned, Some(&dir.join("backups"))).unwrap();
+        assert_eq!(changes[0].before.as_deref(), Some(original.as_str()));
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), original.lines().count() - 1);
+        assert!(!content.contains("[db]:2222"));
+        assert_eq!(fs::read_dir(dir.join("backups")).unwrap().count(), 1);
+        // The line isn't there anymore
+        assert!(remove_entries(&pinned, None).is_err());
+
+        // The file holds hashed names, so the new one is hashed too
+        pin_keys(&path, &host_name("db", 2222), &[key("plain")], None).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.lines().last().unwrap().starts_with("|1|"));
+        let known_hosts = KnownHosts::load(&paths);
+        assert_eq!(
+            known_hosts.check(&host_name("db", 2222), &[key("plain")]),
+            HostKeyCheck::Match
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
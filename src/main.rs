@@ -1,22 +1,65 @@
+mod agent;
 mod app;
+mod askpass;
+mod backup;
+mod config_file;
+mod deploy;
 mod event_handler;
+mod events;
+mod history;
+mod keys;
+mod known_hosts;
+mod popup;
+mod probe;
 mod render;
+mod resolver;
+mod secret;
 mod server;
 mod ssh_login;
+#[cfg(test)]
+mod test_util;
+mod tunnel;
+mod vault;
 
 use crate::app::App;
+use crate::askpass::read_tty;
+use crate::backup::{backup_dir, diff, format_diff, list_backups, DiffLine};
+use crate::config_file::ConfigEditor;
 use crate::event_handler::{handle_key, handle_mouse};
+use crate::events::{AppEvent, Events};
 use crate::render::render;
+use crate::server::{ServerItem, ServerList};
 use crate::ssh_login::ssh_login;
+use crate::vault::{vault_path, Vault};
 use color_eyre::Result;
 use crossterm::event;
 use crossterm::event::Event;
 use crossterm::terminal;
 use ratatui::DefaultTerminal;
+use std::fs;
+use std::io::{self, Write};
+use std::time::Duration;
+
+// How long to wait for input before checking background work again
+const TICK_INTERVAL: Duration = Duration::from_millis(200);
 
 fn main() -> Result<()> {
+    // ssh runs lazyssh again as its askpass program to get a host's password
+    if let Some(socket) = std::env::var_os(askpass::SOCKET_ENV) {
+        askpass::run(socket, std::env::args().nth(1));
+    }
     color_eyre::install()?;
 
+    if std::env::args().any(|arg| arg == "--check") {
+        check();
+    }
+    if std::env::args().nth(1).as_deref() == Some("restore") {
+        restore(std::env::args().nth(2));
+    }
+    if std::env::args().nth(1).as_deref() == Some("migrate-passwords") {
+        migrate_passwords();
+    }
+
     // Enable mouse support
     terminal::enable_raw_mode()?;
     crossterm::execute!(
@@ -26,7 +69,10 @@ fn main() -> Result<()> {
     )?;
 
     let terminal = ratatui::init();
-    let app = App::new()?;
+    let mut app = App::new()?;
+    if std::env::args().any(|arg| arg == "--resolve") {
+        app.start_resolver();
+    }
     let (result, app) = run(app, terminal);
     ratatui::restore();
 
@@ -40,28 +86,218 @@ fn main() -> Result<()> {
 
     if app.has_selected {
         if let Some(server) = app.server_list.selected() {
-            ssh_login(&server);
+            ssh_login(&app.with_secret(server), &app.server_list.config_path);
         }
     }
 
     result
 }
 
+/// Prints the problems found in the config and exits, with a failure status if there are any.
+fn check() -> ! {
+    let server_list = ServerList::from_ssh_config();
+    if server_list.warnings.is_empty() {
+        println!("No problems found");
+        std::process::exit(0);
+    }
+    for warning in &server_list.warnings {
+        println!("{}", warning);
+    }
+    std::process::exit(1);
+}
+
+/// Lists the backups of the config files with the changes restoring them would make, or restores
+/// the backup numbered `number` after showing its changes and asking for confirmation.
+fn restore(number: Option<String>) -> ! {
+    let backups = backup_dir()
+        .map(|dir| list_backups(&dir))
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("Cannot read backups: {}", e);
+            std::process::exit(1);
+        })
+        .unwrap_or_default();
+    if backups.is_empty() {
+        println!("No backups found");
+        std::process::exit(0);
+    }
+
+    let Some(number) = number else {
+        for (i, backup) in backups.iter().enumerate() {
+            let current = fs::read_to_string(&backup.original).unwrap_or_default();
+            let content = fs::read_to_string(&backup.path).unwrap_or_default();
+            let lines = diff(&current, &content);
+            let added = lines
+                .iter()
+                .filter(|l| matches!(l, DiffLine::Added(_)))
+                .count();
+            let removed = lines
+                .iter()
+                .filter(|l| matches!(l, DiffLine::Removed(_)))
+                .count();
+            println!(
+                "{:>3}  {}  {}  +{} -{}",
+                i + 1,
+                backup.time.format("%Y-%m-%d %H:%M:%S"),
+                backup.original.display(),
+                added,
+                removed
+            );
+        }
+        println!("\nRun `lazyssh restore <number>` to see the changes of a backup and restore it");
+        std::process::exit(0);
+    };
+
+    let Some(backup) = number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| backups.get(i))
+    else {
+        eprintln!("No backup numbered {}", number);
+        std::process::exit(1);
+    };
+    let current = fs::read_to_string(&backup.original).unwrap_or_default();
+    let content = fs::read_to_string(&backup.path).unwrap_or_default();
+    let lines = diff(&current, &content);
+    if lines.iter().all(|line| matches!(line, DiffLine::Same(_))) {
+        println!("{} is the same as this backup", backup.original.display());
+        std::process::exit(0);
+    }
+    print!("{}", format_diff(&lines));
+    print!(
+        "\nRestore {} as of {}? [y/N] ",
+        backup.original.display(),
+        backup.time.format("%Y-%m-%d %H:%M:%S")
+    );
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        std::process::exit(0);
+    }
+
+    let dir = backup_dir().unwrap_or_default();
+    match backup::restore(backup, &dir) {
+        Ok(()) => {
+            println!("Restored, the previous version was backed up");
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Cannot restore: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Moves the `#: Password` comments of the config into the vault, creating it if needed.
+fn migrate_passwords() -> ! {
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+    let Some(path) = vault_path() else {
+        fail("Cannot find the local data directory to keep the vault in".to_string());
+    };
+    let list = ServerList::from_ssh_config();
+    let servers: Vec<&ServerItem> = list
+        .items
+        .iter()
+        .filter(|item| !item.is_group && item.source.is_some() && item.password.is_some())
+        .collect();
+    if servers.is_empty() {
+        println!("No `#: Password` found in the config");
+        std::process::exit(0);
+    }
+
+    let mut vault = if path.exists() {
+        let passphrase =
+            read_tty("Vault passphrase: ", false).unwrap_or_else(|e| fail(e.to_string()));
+        Vault::open(&path, &passphrase).unwrap_or_else(|e| fail(e.to_string()))
+    } else {
+        println!("Creating the vault at {}", path.display());
+        let passphrase =
+            read_tty("New vault passphrase: ", false).unwrap_or_else(|e| fail(e.to_string()));
+        let repeated =
+            read_tty("Repeat the passphrase: ", false).unwrap_or_else(|e| fail(e.to_string()));
+        if passphrase.is_empty() {
+            fail("The passphrase cannot be empty".to_string());
+        }
+        if passphrase != repeated {
+            fail("The passphrases don't match".to_string());
+        }
+        Vault::create(&path, &passphrase).unwrap_or_else(|e| fail(e.to_string()))
+    };
+    for server in &servers {
+        vault.set(&server.host, server.password.as_deref().unwrap_or_default());
+    }
+    // The vault is written first so no password is lost if the config can't be
+    if let Err(e) = vault.save() {
+        fail(format!("Cannot write the vault: {}", e));
+    }
+
+    // Not backed up, the backups would keep the passwords
+    let mut editor = ConfigEditor::default();
+    for server in &servers {
+        if let Err(e) = editor.remove_password(server) {
+            fail(format!(
+                "Cannot remove the password of {}: {}",
+                server.host, e
+            ));
+        }
+    }
+    if let Err(e) = editor.save() {
+        fail(format!("Cannot write the config: {}", e));
+    }
+    println!(
+        "Moved the passwords of {} hosts to the vault",
+        servers.len()
+    );
+    if let Some(dir) = backup_dir().filter(|dir| dir.exists()) {
+        println!(
+            "Backups made before in {} may still contain them",
+            dir.display()
+        );
+    }
+    std::process::exit(0);
+}
+
 fn run(mut app: App, mut terminal: DefaultTerminal) -> (Result<()>, App) {
     let mut result = Ok(());
+    // Dropped before returning, which stops reading the terminal
+    let events = Events::new(TICK_INTERVAL);
+    app.start_workers(events.sender());
 
     while !app.should_exit {
         if let Err(e) = terminal.draw(|frame| render(frame, &mut app)) {
             result = Err(e.into());
             break;
         }
-        if let Ok(event) = event::read() {
+        // Handles everything that is already waiting before drawing again
+        let mut next = events.next();
+        while let Some(event) = next {
             match event {
-                Event::Key(key) => handle_key(&mut app, key),
-                Event::Mouse(mouse) => handle_mouse(&mut app, mouse),
-                _ => {}
+                AppEvent::Input(Event::Key(key)) => handle_key(&mut app, key),
+                AppEvent::Input(Event::Mouse(mouse)) => handle_mouse(&mut app, mouse),
+                AppEvent::Input(_) => {}
+                AppEvent::Tick => {
+                    app.poll_resolver();
+                    app.poll_tunnels();
+                    app.probe_visible();
+                }
+                AppEvent::Probe(result) => app.apply_probe(result),
+                AppEvent::Deployed(deployment) => app.apply_deployment(*deployment),
+                AppEvent::KeyDone(result) => app.apply_key_result(result),
+            }
+            // Whatever opened a popup, a drag started before it must not drop a host after it
+            if app.popup.is_some() {
+                app.drag_from = None;
             }
-        };
+            if app.should_exit {
+                break;
+            }
+            next = events.try_next();
+        }
     }
 
     (result, app)
@@ -0,0 +1,287 @@
+use crate::config_file::HostSettings;
+use crate::known_hosts::{HostKey, KnownHost};
+use crate::server::{
+    group_path, is_reserved_group, is_wildcard, ServerItem, ServerList, OTHER_GROUP,
+};
+
+pub const HOST_FIELDS: [&str; 7] = [
+    "Host",
+    "HostName",
+    "User",
+    "Port",
+    "IdentityFile",
+    "Group",
+    "Password",
+];
+const PASSWORD_FIELD: usize = 6;
+
+/// A window drawn over the current view that takes the keys until it is closed.
+#[derive(Debug)]
+pub enum Popup {
+    HostForm(Box<HostForm>),
+    Input(Input),
+    Confirm(Confirm),
+}
+
+#[derive(Debug)]
+pub struct HostForm {
+    /// The host being edited, `None` when adding one.
+    pub editing: Option<ServerItem>,
+    /// One value per entry of `HOST_FIELDS`.
+    pub values: Vec<String>,
+    pub focused: usize,
+    pub error: Option<String>,
+}
+
+/// A single line of text, such as a group name.
+#[derive(Debug)]
+pub struct Input {
+    pub title: String,
+    pub value: String,
+    pub action: InputAction,
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum InputAction {
+    AddGroup,
+    /// Renames the group with that name.
+    RenameGroup(String),
+    /// Unlocks the vault with the passphrase, then carries on with the action that needed it.
+    UnlockVault(VaultAction),
+    /// How long ssh-agent keeps the key `key`, asked before its passphrase.
+    KeyLifetime(AddKey),
+    KeyPassphrase(AddKey),
+    /// The type of a new key pair, asked before its path and passphrase.
+    NewKeyType,
+    NewKeyPath(NewKey),
+    NewKeyPassphrase(NewKey),
+    /// Installs the key with that path on the host, then makes it log in with it.
+    DeployKey(Box<ServerItem>),
+}
+
+/// A key to load into ssh-agent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddKey {
+    /// The `IdentityFile` as written in the config.
+    pub key: String,
+    pub lifetime: Option<String>,
+    /// The host to log in to once the key is loaded.
+    pub login: Option<String>,
+}
+
+/// A key pair to generate with `ssh-keygen`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewKey {
+    pub key_type: String,
+    /// The private key, as given with `~`.
+    pub path: String,
+}
+
+/// What needed the vault to be unlocked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VaultAction {
+    Login,
+    StartTunnel,
+    AddHost,
+    EditHost,
+    DeployKey,
+}
+
+/// A yes/no question about `action`.
+#[derive(Debug)]
+pub struct Confirm {
+    pub message: String,
+    pub action: ConfirmAction,
+}
+
+#[derive(Debug)]
+pub enum ConfirmAction {
+    DeleteHost(Box<ServerItem>),
+    DeleteGroup(String),
+    /// Loads a key into ssh-agent, answering no logs in without it when there is a host to log in to.
+    AddKey(AddKey),
+    /// Logs in to a host whose key doesn't match known_hosts.
+    HostKeyChanged(Box<ServerItem>),
+    /// Removes these known_hosts keys of a host.
+    ForgetHostKeys(Box<ServerItem>, Vec<KnownHost>),
+    /// Pins the `type blob` keys a host presents.
+    PinHostKeys(Box<ServerItem>, Vec<HostKey>),
+    RemoveKnownHost(Box<KnownHost>),
+}
+
+impl HostForm {
+    pub fn new(editing: Option<ServerItem>, settings: HostSettings) -> Self {
+        Self {
+            editing,
+            values: vec![
+                settings.host,
+                settings.hostname,
+                settings.user,
+                settings.port,
+                settings.identity_file,
+                settings.group,
+                settings.password,
+            ],
+            focused: 0,
+            error: None,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match &self.editing {
+            Some(server) => format!(" Edit {} ", server.host),
+            None => " Add host ".to_string(),
+        }
+    }
+
+    pub fn is_masked(field: usize) -> bool {
+        field == PASSWORD_FIELD
+    }
+
+    pub fn focus_next(&mut self) {
+        self.focused = (self.focused + 1) % self.values.len();
+    }
+
+    pub fn focus_previous(&mut self) {
+        self.focused = (self.focused + self.values.len() - 1) % self.values.len();
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.values[self.focused].push(c);
+        self.error = None;
+    }
+
+    pub fn pop(&mut self) {
+        self.values[self.focused].pop();
+        self.error = None;
+    }
+
+    /// Checks the values against the hosts of `list`, returning the settings to write.
+    pub fn validate(&self, list: &ServerList) -> Result<HostSettings, String> {
+        let values: Vec<&str> = self.values.iter().map(|value| value.trim()).collect();
+        let [host, hostname, user, port, identity_file, group, password] = values[..] else {
+            unreachable!()
+        };
+
+        let editing_host = self.editing.as_ref().map(|server| server.host.as_str());
+        if host.is_empty() {
+            return Err("Host is required".to_string());
+        }
+        // Only legacy names marked with `#: SpacedName` may contain spaces
+        let spaced = editing_host.is_some_and(|host| host.contains(char::is_whitespace));
+        if host.contains(char::is_whitespace) && !spaced {
+            return Err("Host cannot contain spaces".to_string());
+        }
+        if is_wildcard(host) || host.starts_with('!') {
+            return Err("Host cannot be a pattern".to_string());
+        }
+        let exists = list
+            .items
+            .iter()
+            .any(|item| !item.is_group && item.host == host);
+        if exists && editing_host != Some(host) {
+            return Err(format!("`{}` already exists", host));
+        }
+        if hostname.contains(char::is_whitespace) || user.contains(char::is_whitespace) {
+            return Err("HostName and User cannot contain spaces".to_string());
+        }
+        if !port.is_empty() && !port.parse::<u16>().is_ok_and(|port| port > 0) {
+            return Err("Port must be a number between 1 and 65535".to_string());
+        }
+
+        Ok(HostSettings {
+            host: host.to_string(),
+            hostname: hostname.to_string(),
+            user: user.to_string(),
+            port: port.to_string(),
+            identity_file: identity_file.to_string(),
+            group: group_path(group),
+            password: password.to_string(),
+        })
+    }
+}
+
+impl Input {
+    pub fn is_masked(&self) -> bool {
+        matches!(
+            self.action,
+            InputAction::UnlockVault(_)
+                | InputAction::KeyPassphrase(_)
+                | InputAction::NewKeyPassphrase(_)
+        )
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.value.push(c);
+        self.error = None;
+    }
+
+    pub fn pop(&mut self) {
+        self.value.pop();
+        self.error = None;
+    }
+}
+
+/// Checks a new group name against the groups of `list`.
+pub fn validate_group(name: &str, list: &ServerList) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Name is required".to_string());
+    }
+    // The hosts without group are listed as `other`
+    if is_reserved_group(name) {
+        return Err(format!("`{}` is reserved", OTHER_GROUP));
+    }
+    if !list.group_markers(name).is_empty() {
+        return Err(format!("Group `{}` already exists", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config_file::HostSettings;
+    use crate::popup::HostForm;
+    use crate::server::ServerList;
+    use crate::test_util::fixture;
+
+    #[test]
+    fn test_validate() {
+        let list = ServerList::from_config_file(&fixture("multi/config"));
+        let form = |host: &str, port: &str| {
+            let settings = HostSettings {
+                host: host.to_string(),
+                port: port.to_string(),
+                ..HostSettings::default()
+            };
+            HostForm::new(None, settings)
+        };
+
+        let settings = form(" cache1 ", "2222").validate(&list).unwrap();
+        assert_eq!(settings.host, "cache1");
+        assert_eq!(settings.port, "2222");
+
+        assert!(form("", "").validate(&list).is_err());
+        assert!(form("web1", "").validate(&list).is_err());
+        assert!(form("cache 1", "").validate(&list).is_err());
+        assert!(form("cache*", "").validate(&list).is_err());
+        assert!(form("cache1", "ssh").validate(&list).is_err());
+        assert!(form("cache1", "0").validate(&list).is_err());
+
+        // Keeping its own name while editing
+        let web1 = list.items.iter().find(|i| i.host == "web1").cloned();
+        let mut edit = form("web1", "");
+        edit.editing = web1;
+        assert!(edit.validate(&list).is_ok());
+
+        // Legacy names with spaces can be kept
+        let media = list
+            .items
+            .iter()
+            .find(|i| i.host == "Media server")
+            .cloned();
+        let mut edit = form("Media server", "");
+        edit.editing = media;
+        assert!(edit.validate(&list).is_ok());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::keys::{KeyJob, KeyResult};
+use crate::known_hosts::{resolve, scan_host_keys, HostKey};
+use crate::server::ServerItem;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const BANNER_TIMEOUT: Duration = Duration::from_secs(2);
+// RFC 4253 limits the identification line to 255 characters, a few lines may come before it
+const MAX_PREAMBLE_LEN: usize = 8 * 255;
+
+/// A host to check, reached directly at `address:port`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub host: String,
+    pub address: String,
+    pub port: u16,
+}
+
+impl Target {
+    /// Where to check `server`, `None` for groups and hosts only reachable through a proxy.
+    pub fn of(server: &ServerItem) -> Option<Self> {
+        if server.is_group || server.proxy_jump.is_some() || server.proxy_command.is_some() {
+            return None;
+        }
+        Some(Self {
+            host: server.host.clone(),
+            address: server.hostname().to_string(),
+            port: u16::try_from(server.port).ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeStatus {
+    /// The port accepted the connection after `latency`. `version` is the software of the SSH
+    /// server, `None` if it didn't greet like one.
+    Up {
+        latency: Duration,
+        version: Option<String>,
+    },
+    Down(String),
+}
+
+/// What a worker does for a host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Job {
+    Probe(Target),
+    /// Fetches the keys the host presents with ssh-keyscan.
+    ScanKeys(Target),
+    /// Looks up the IP addresses of the host.
+    Resolve(Target),
+    /// Generates a key or loads one into ssh-agent, reported as a `KeyResult`.
+    Key(KeyJob),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Report {
+    Status(ProbeStatus),
+    /// The `type blob` keys the host presents, or why they couldn't be fetched.
+    HostKeys(Result<Vec<HostKey>, String>),
+    Addresses(Vec<IpAddr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeResult {
+    pub host: String,
+    pub report: Report,
+}
+
+/// Checks hosts on a pool of worker threads, sending each result as soon as it is known.
+#[derive(Debug)]
+pub struct Prober {
+    jobs: Sender<Job>,
+}
+
+impl Prober {
+    /// Starts `workers` threads sending their results to `results`. They stop once the prober is
+    /// dropped or `results` is disconnected.
+    pub fn new<T: From<ProbeResult> + From<KeyResult> + Send + 'static>(
+        workers: usize,
+        results: Sender<T>,
+    ) -> Self {
+        let (jobs, queue) = mpsc::channel();
+        let queue: Arc<Mutex<Receiver<Job>>> = Arc::new(Mutex::new(queue));
+        for _ in 0..workers {
+            let queue = Arc::clone(&queue);
+            let results = results.clone();
+            thread::spawn(move || loop {
+                // The lock is only held while waiting for the next job
+                let Ok(job) = queue.lock().unwrap().recv() else {
+                    return;
+                };
+                let result: T = match job {
+                    Job::Probe(target) => ProbeResult {
+                        report: Report::Status(probe(&target.address, target.port)),
+                        host: target.host,
+                    }
+                    .into(),
+                    Job::ScanKeys(target) => ProbeResult {
+                        report: Report::HostKeys(
+                            scan_host_keys(&target.address, target.port).map_err(|e| e.to_string()),
+                        ),
+                        host: target.host,
+                    }
+                    .into(),
+                    Job::Resolve(target) => ProbeResult {
+                        report: Report::Addresses(resolve(&target.address)),
+                        host: target.host,
+                    }
+                    .into(),
+                    Job::Key(job) => job.run().into(),
+                };
+                if results.send(result).is_err() {
+                    return;
+                }
+            });
+        }
+        Self { jobs }
+    }
+
+    pub fn probe(&self, target: Target) {
+        let _ = self.jobs.send(Job::Probe(target));
+    }
+
+    pub fn scan_keys(&self, target: Target) {
+        let _ = self.jobs.send(Job::ScanKeys(target));
+    }
+
+    pub fn resolve(&self, target: Target) {
+        let _ = self.jobs.send(Job::Resolve(target));
+    }
+
+    pub fn run_key_job(&self, job: KeyJob) {
+        let _ = self.jobs.send(Job::Key(job));
+    }
+}
+
+/// Connects to `address:port` and reads the SSH identification line the server sends first.
+pub fn probe(address: &str, port: u16) -> ProbeStatus {
+    let addrs: Vec<SocketAddr> = match (address, port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => return ProbeStatus::Down(e.to_string()),
+    };
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "no address");
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                return ProbeStatus::Up {
+                    latency: started.elapsed(),
+                    version: read_banner(stream).ok().flatten(),
+                }
+            }
+            Err(e) => error = e,
+        }
+    }
+    ProbeStatus::Down(error.to_string())
+}
+
+/// The software version from the `SSH-protoversion-softwareversion comments` line, servers may
+/// send other lines before it.
+fn read_banner(mut stream: TcpStream) -> io::Result<Option<String>> {
+    stream.set_read_timeout(Some(BANNER_TIMEOUT))?;
+    let mut data = Vec::new();
+    let mut buffer = [0; 256];
+    loop {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&data);
+        // The last piece isn't a whole line yet
+        let mut lines: Vec<&str> = text.split('\n').collect();
+        lines.pop();
+        if let Some(line) = lines.iter().find(|line| line.starts_with("SSH-")) {
+            return Ok(parse_banner(line));
+        }
+        if data.len() > MAX_PREAMBLE_LEN {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+fn parse_banner(line: &str) -> Option<String> {
+    let rest = line.trim_end_matches('\r').strip_prefix("SSH-")?;
+    let (_protocol, software) = rest.split_once('-')?;
+    let version = software.split_whitespace().next()?;
+    Some(version.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::events::AppEvent;
+    use crate::probe::{parse_banner, probe, ProbeResult, ProbeStatus, Prober, Report, Target};
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Greets like sshd, once for the direct probe and once for the prober
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream
+                    .write_all(b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n")
+                    .unwrap();
+            }
+        });
+
+        match probe("127.0.0.1", port) {
+            ProbeStatus::Up { version, .. } => {
+                assert_eq!(version.as_deref(), Some("OpenSSH_9.6p1"))
+            }
+            status => panic!("unexpected {:?}", status),
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let prober = Prober::new(2, tx);
+        let next = || -> ProbeResult {
+            match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+                AppEvent::Probe(result) => result,
+                event => panic!("unexpected {:?}", event),
+            }
+        };
+        prober.probe(Target {
+            host: "local".to_string(),
+            address: "127.0.0.1".to_string(),
+            port,
+        });
+        let result = next();
+        assert_eq!(result.host, "local");
+        assert!(matches!(
+            result.report,
+            Report::Status(ProbeStatus::Up { .. })
+        ));
+        server.join().unwrap();
+
+        // Nothing listens there anymore
+        prober.probe(Target {
+            host: "closed".to_string(),
+            address: "127.0.0.1".to_string(),
+            port,
+        });
+        let result = next();
+        assert!(matches!(
+            result.report,
+            Report::Status(ProbeStatus::Down(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_banner() {
+        assert_eq!(
+            parse_banner("SSH-2.0-dropbear_2022.83\r").as_deref(),
+            Some("dropbear_2022.83")
+        );
+        assert_eq!(
+            parse_banner("SSH-1.99-Cisco-1.25").as_deref(),
+            Some("Cisco-1.25")
+        );
+        assert_eq!(parse_banner("HTTP/1.1 400 Bad Request"), None);
+    }
+}
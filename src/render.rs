@@ -1,20 +1,245 @@
-use crate::app::App;
-use ratatui::layout::{Constraint, Direction, Layout};
+use crate::app::{App, View};
+use crate::keys::{display_path, fingerprint, key_type, KeyInfo};
+use crate::known_hosts::{display_type, HostKeyCheck, HostPatterns, KnownStatus};
+use crate::popup::{ConfirmAction, HostForm, InputAction, Popup, HOST_FIELDS};
+use crate::probe::{ProbeStatus, Target};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::prelude::{Color, Modifier, Style};
 use ratatui::style::palette::tailwind::SLATE;
-use ratatui::text::Line;
-use ratatui::widgets::{HighlightSpacing, List, ListItem, Paragraph};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{
+    Block, Borders, Clear, HighlightSpacing, List, ListItem, Paragraph, Row, Table, Wrap,
+};
 use ratatui::Frame;
+use std::time::Duration;
+use unicode_width::UnicodeWidthStr;
 
 const SELECTED_STYLE: Style = Style::new().bg(SLATE.c800).add_modifier(Modifier::BOLD);
 const TEXT_FG_COLOR: Color = SLATE.c200;
+const WARNING_FG_COLOR: Color = Color::Yellow;
+const OK_FG_COLOR: Color = Color::Green;
+const ERROR_FG_COLOR: Color = Color::Red;
+const LABEL_FG_COLOR: Color = SLATE.c400;
+// Shown next to values OpenSSH falls back to because the config doesn't set them
+const DEFAULT_FG_COLOR: Color = SLATE.c500;
+// Height of the diagnostics panel including its borders
+const DIAGNOSTICS_HEIGHT: u16 = 10;
+const POPUP_WIDTH: u16 = 60;
 
 pub fn render(frame: &mut Frame, app: &mut App) {
+    let diagnostics_height = if app.show_diagnostics {
+        DIAGNOSTICS_HEIGHT
+    } else {
+        0
+    };
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Fill(1), Constraint::Length(1)])
+        .constraints([
+            Constraint::Fill(1),
+            Constraint::Length(diagnostics_height),
+            Constraint::Length(1),
+        ])
         .split(frame.area());
 
+    match app.view {
+        View::Servers => render_servers(frame, chunks[0], app),
+        View::Tunnels => render_tunnels(frame, chunks[0], app),
+        View::Keys => render_keys(frame, chunks[0], app),
+        View::KnownHosts => render_known_hosts(frame, chunks[0], app),
+    }
+
+    // render diagnostics
+    if app.show_diagnostics {
+        let warnings = &app.server_list.warnings;
+        let lines: Vec<Line> = if warnings.is_empty() {
+            vec![Line::styled("No problems found", TEXT_FG_COLOR)]
+        } else {
+            warnings
+                .iter()
+                .map(|warning| Line::styled(warning.to_string(), WARNING_FG_COLOR))
+                .collect()
+        };
+        let diagnostics = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::TOP)
+                .title(format!(" Diagnostics ({}) ", warnings.len())),
+        );
+        frame.render_widget(diagnostics, chunks[1]);
+    }
+
+    // render footer
+    let footer_text = if let Some(message) = &app.message {
+        message.clone()
+    } else if let Some(Popup::HostForm(_)) = app.popup {
+        "Tab/↓: next field | Shift+Tab/↑: previous field | Enter: save | Esc: cancel".to_string()
+    } else if let Some(Popup::Input(input)) = &app.popup {
+        match input.action {
+            InputAction::UnlockVault(_) => "Enter: unlock | Esc: continue without the vault",
+            InputAction::KeyLifetime(_) => {
+                "Enter: next, empty to keep the key until the agent stops | Esc: cancel"
+            }
+            InputAction::KeyPassphrase(_) => {
+                "Enter: add key, empty if it has no passphrase | Esc: cancel"
+            }
+            InputAction::NewKeyType | InputAction::NewKeyPath(_) => "Enter: next | Esc: cancel",
+            InputAction::DeployKey(_) => {
+                "Enter: install the key and log in with it from now on | Esc: cancel"
+            }
+            InputAction::NewKeyPassphrase(_) => {
+                "Enter: generate key, empty for no passphrase | Esc: cancel"
+            }
+            _ => "Enter: save | Esc: cancel",
+        }
+        .to_string()
+    } else if let Some(Popup::Confirm(confirm)) = &app.popup {
+        match &confirm.action {
+            ConfirmAction::AddKey(add_key) if add_key.login.is_some() => {
+                "y/Enter: add key | n: log in without it | Esc: cancel"
+            }
+            ConfirmAction::HostKeyChanged(_) => "y/Enter: connect | n/Esc: cancel",
+            ConfirmAction::PinHostKeys(..) => "y/Enter: pin | n/Esc: leave them unpinned",
+            _ => "y/Enter: yes | n/Esc: no",
+        }
+        .to_string()
+    } else if app.view == View::Tunnels {
+        "j/↓: down | k/↑: up | s: stop | r: restart | Esc/T: back".to_string()
+    } else if app.view == View::Keys {
+        "j/↓: down | k/↑: up | n: new key | A: add key to agent | Esc/i: back".to_string()
+    } else if app.view == View::KnownHosts {
+        "j/↓: down | k/↑: up | d: remove | Esc/H: back".to_string()
+    } else if !app.clipboard.is_empty() {
+        format!(
+            "{} cut | x: cut/uncut | p: move after selection | Esc: cancel",
+            app.clipboard.len()
+        )
+    } else if app.is_searching {
+        format!(
+            "Search: {} (Press Esc to cancel, Ctrl+j/k or ↑/↓ to navigate)",
+            app.search_query
+        )
+    } else {
+        format!(
+            "j/↓: down | k/↑: up | g/Home: top | G/End: bottom | / or f: search | z: toggle all groups | t: tunnel | T: tunnels ({}) | i: keys | H: unmatched known_hosts | R: forget host key | a/e/d: add/edit/delete | A: add key to agent | c: install key | n/r: new/rename group | J/K: move group | x/p: cut/paste | u/Ctrl+r: undo/redo | w: diagnostics ({}) | Enter: login",
+            app.tunnels.tunnels.len(),
+            app.server_list.warnings.len()
+        )
+    };
+    let footer = Paragraph::new(footer_text);
+
+    frame.render_widget(footer, chunks[2]);
+
+    match &app.popup {
+        Some(Popup::HostForm(form)) => render_host_form(frame, form),
+        Some(Popup::Input(input)) => {
+            let mut lines = vec![Line::from(vec![
+                Span::styled(
+                    if input.is_masked() {
+                        format!(" {}", "*".repeat(input.value.chars().count()))
+                    } else {
+                        format!(" {}", input.value)
+                    },
+                    TEXT_FG_COLOR,
+                ),
+                Span::styled("▏", TEXT_FG_COLOR),
+            ])];
+            if let Some(error) = &input.error {
+                lines.push(Line::styled(format!(" {}", error), ERROR_FG_COLOR));
+            }
+            let area = popup_area(frame.area(), lines.len() as u16 + 2);
+            let paragraph = Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(input.title.as_str()),
+            );
+            frame.render_widget(Clear, area);
+            frame.render_widget(paragraph, area);
+        }
+        Some(Popup::Confirm(confirm)) => {
+            let width = POPUP_WIDTH.min(frame.area().width).saturating_sub(2);
+            let area = popup_area(frame.area(), wrapped_height(&confirm.message, width) + 2);
+            let paragraph = Paragraph::new(Line::styled(&confirm.message, TEXT_FG_COLOR))
+                .wrap(Wrap { trim: true })
+                .block(Block::default().borders(Borders::ALL).title(" Confirm "));
+            frame.render_widget(Clear, area);
+            frame.render_widget(paragraph, area);
+        }
+        None => {}
+    }
+}
+
+/// The number of lines `text` takes once wrapped at word boundaries to `width` columns.
+fn wrapped_height(text: &str, width: u16) -> u16 {
+    let width = usize::from(width.max(1));
+    let mut lines = 1;
+    let mut column = 0;
+    for word in text.split_whitespace() {
+        let len = word.width();
+        if column > 0 && column + 1 + len > width {
+            lines += 1;
+            column = 0;
+        }
+        if column > 0 {
+            column += 1;
+        }
+        // Words longer than a line are cut
+        lines += len.saturating_sub(1) / width;
+        column += len % width;
+    }
+    lines as u16
+}
+
+/// A rectangle of `height` lines centered in `area`.
+fn popup_area(area: Rect, height: u16) -> Rect {
+    let width = POPUP_WIDTH.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn render_host_form(frame: &mut Frame, form: &HostForm) {
+    let mut lines: Vec<Line> = HOST_FIELDS
+        .iter()
+        .zip(&form.values)
+        .enumerate()
+        .map(|(i, (label, value))| {
+            let value = if HostForm::is_masked(i) {
+                "*".repeat(value.chars().count())
+            } else {
+                value.clone()
+            };
+            let mut line = Line::from(vec![
+                Span::styled(format!(" {:<14}", label), LABEL_FG_COLOR),
+                Span::styled(value, TEXT_FG_COLOR),
+            ]);
+            if i == form.focused {
+                line.push_span(Span::styled("▏", TEXT_FG_COLOR));
+                line = line.style(SELECTED_STYLE);
+            }
+            line
+        })
+        .collect();
+    if let Some(error) = &form.error {
+        lines.push(Line::default());
+        lines.push(Line::styled(format!(" {}", error), ERROR_FG_COLOR));
+    }
+
+    let area = popup_area(frame.area(), HOST_FIELDS.len() as u16 + 4);
+    let paragraph =
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(form.title()));
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
+
+fn render_servers(frame: &mut Frame, area: Rect, app: &mut App) {
+    let main_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+        .split(area);
+
     // render server list
     let max_host_len = app.server_list.max_host_len();
     let items: Vec<ListItem> = app
@@ -27,29 +252,375 @@ pub fn render(frame: &mut Frame, app: &mut App) {
             } else {
                 true
             };
-            ListItem::new(Line::styled(
-                server.to_string_aligned(max_host_len, is_expanded),
-                TEXT_FG_COLOR,
-            ))
+            // Cut hosts are dimmed until they are pasted
+            let is_cut =
+                !server.is_group && app.clipboard.iter().any(|cut| cut.host == server.host);
+            let color = if is_cut {
+                DEFAULT_FG_COLOR
+            } else {
+                TEXT_FG_COLOR
+            };
+            // Whether the host answers, blank for groups and hosts behind a proxy
+            let status = match app.probe_status(server) {
+                _ if Target::of(server).is_none() => Span::raw("  "),
+                Some(ProbeStatus::Up { .. }) => Span::styled("● ", OK_FG_COLOR),
+                Some(ProbeStatus::Down(_)) => Span::styled("● ", ERROR_FG_COLOR),
+                None => Span::styled("○ ", DEFAULT_FG_COLOR),
+            };
+            let mut line = Line::from(vec![
+                status,
+                Span::styled(server.to_string_aligned(max_host_len, is_expanded), color),
+            ]);
+            // Whether ssh-agent holds the host's key
+            match app.key_loaded(server) {
+                Some(true) => line.push_span(Span::styled(" ●", OK_FG_COLOR)),
+                Some(false) => line.push_span(Span::styled(" ○", WARNING_FG_COLOR)),
+                None => {}
+            }
+            ListItem::new(line)
         })
         .collect();
     let list = List::new(items)
         .highlight_style(SELECTED_STYLE)
         .highlight_symbol("→ ")
         .highlight_spacing(HighlightSpacing::Always);
-    frame.render_stateful_widget(list, chunks[0], &mut app.server_list.state);
+    frame.render_stateful_widget(list, main_chunks[0], &mut app.server_list.state);
+    // The list has no border nor header, its rows start at the top of the area
+    app.list_area = main_chunks[0];
 
-    // render footer
-    let footer_text = if app.is_searching {
-        format!(
-            "Search: {} (Press Esc to cancel, Ctrl+j/k or ↑/↓ to navigate)",
-            app.search_query
-        )
+    render_details(frame, main_chunks[1], app);
+}
+
+/// Lists the running tunnels with their local ports, a filled dot marking a port accepting
+/// connections.
+fn render_tunnels(frame: &mut Frame, area: Rect, app: &mut App) {
+    let header = Row::new([
+        "Host",
+        "PID",
+        "Local ports",
+        "Uptime",
+        "Restarts",
+        "Forwards",
+    ])
+    .style(Style::new().fg(LABEL_FG_COLOR));
+    let rows: Vec<Row> = app
+        .tunnels
+        .tunnels
+        .iter()
+        .map(|tunnel| {
+            let ports: Vec<Span> = tunnel
+                .ports
+                .iter()
+                .map(|(address, open)| {
+                    let (dot, color) = if *open {
+                        ("●", OK_FG_COLOR)
+                    } else {
+                        ("○", ERROR_FG_COLOR)
+                    };
+                    Span::styled(format!("{} {} ", dot, address.port()), color)
+                })
+                .collect();
+            let (pid, uptime) = match (tunnel.pid(), tunnel.exited_at) {
+                (Some(pid), None) => (pid.to_string(), format_duration(tunnel.started.elapsed())),
+                _ => ("-".to_string(), "restarting".to_string()),
+            };
+            let forwards: Vec<String> = tunnel
+                .server
+                .forwards
+                .iter()
+                .map(|forward| forward.to_string())
+                .collect();
+            Row::new(vec![
+                Line::from(tunnel.server.host.clone()),
+                Line::from(pid),
+                Line::from(ports),
+                Line::from(uptime),
+                Line::from(tunnel.restarts.to_string()),
+                Line::from(forwards.join(", ")),
+            ])
+            .style(Style::new().fg(TEXT_FG_COLOR))
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(20),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Fill(2),
+        ],
+    )
+    .header(header)
+    .block(Block::default().title(format!(" Tunnels ({}) ", app.tunnels.tunnels.len())))
+    .row_highlight_style(SELECTED_STYLE)
+    .highlight_symbol("→ ")
+    .highlight_spacing(HighlightSpacing::Always);
+    frame.render_stateful_widget(table, area, &mut app.tunnels.state);
+}
+
+/// Lists the keys of `~/.ssh` and the ones the config names, flagging the keys no host uses
+/// and the ones that don't exist.
+fn render_keys(frame: &mut Frame, area: Rect, app: &mut App) {
+    let header = Row::new([
+        "Key",
+        "Type",
+        "Fingerprint",
+        "Comment",
+        "Passphrase",
+        "Agent",
+        "Hosts",
+    ])
+    .style(Style::new().fg(LABEL_FG_COLOR));
+    let rows: Vec<Row> = app
+        .keys
+        .keys
+        .iter()
+        .map(|key| {
+            let (kind, fingerprint) = match &key.blob {
+                Some(blob) => (
+                    key_type(blob)
+                        .map(|(name, bits)| format!("{} {}", name, bits))
+                        .unwrap_or_else(|| "unknown".to_string()),
+                    fingerprint(blob),
+                ),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            let passphrase = match key.encrypted {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "-",
+            };
+            let agent = match key.blob.as_ref().and_then(|blob| app.agent_holds(blob)) {
+                Some(true) => Span::styled("●", OK_FG_COLOR),
+                Some(false) => Span::styled("○", WARNING_FG_COLOR),
+                None => Span::raw("-"),
+            };
+            Row::new(vec![
+                Line::from(key.display_path()),
+                Line::from(kind),
+                Line::from(fingerprint),
+                Line::from(key.comment.clone()),
+                Line::from(passphrase),
+                Line::from(agent),
+                key_status(key),
+            ])
+            .style(Style::new().fg(TEXT_FG_COLOR))
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(2),
+            Constraint::Length(12),
+            Constraint::Length(51),
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(5),
+            Constraint::Fill(2),
+        ],
+    )
+    .header(header)
+    .block(Block::default().title(format!(" Keys ({}) ", app.keys.keys.len())))
+    .row_highlight_style(SELECTED_STYLE)
+    .highlight_symbol("→ ")
+    .highlight_spacing(HighlightSpacing::Always);
+    frame.render_stateful_widget(table, area, &mut app.keys.state);
+}
+
+/// The known_hosts keys no configured host is looked up by.
+fn render_known_hosts(frame: &mut Frame, area: Rect, app: &mut App) {
+    let title = match app.resolving.len() {
+        0 => format!(
+            " Unmatched known_hosts keys ({}) ",
+            app.unmatched_hosts.entries.len()
+        ),
+        resolving => format!(
+            " Unmatched known_hosts keys ({}, looking up {} hosts) ",
+            app.unmatched_hosts.entries.len(),
+            resolving
+        ),
+    };
+    let header =
+        Row::new(["Host", "Type", "Fingerprint", "File"]).style(Style::new().fg(LABEL_FG_COLOR));
+    let rows: Vec<Row> = app
+        .unmatched_hosts
+        .entries
+        .iter()
+        .map(|entry| {
+            let host = match &entry.hosts {
+                HostPatterns::Plain(patterns) => Span::raw(patterns.clone()),
+                HostPatterns::Hashed { .. } => Span::styled("(hashed)", DEFAULT_FG_COLOR),
+            };
+            Row::new(vec![
+                Line::from(host),
+                Line::from(display_type(&entry.blob, &entry.key_type)),
+                Line::from(fingerprint(&entry.blob)),
+                Line::from(format!("{}:{}", display_path(&entry.path), entry.line)),
+            ])
+            .style(Style::new().fg(TEXT_FG_COLOR))
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(2),
+            Constraint::Length(12),
+            Constraint::Length(51),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(header)
+    .block(Block::default().title(title))
+    .row_highlight_style(SELECTED_STYLE)
+    .highlight_symbol("→ ")
+    .highlight_spacing(HighlightSpacing::Always);
+    frame.render_stateful_widget(table, area, &mut app.unmatched_hosts.state);
+}
+
+/// The hosts using `key`, or what is wrong with it.
+fn key_status(key: &KeyInfo) -> Line<'static> {
+    if key.is_missing() {
+        let hosts = key.hosts.join(", ");
+        return Line::styled(format!("missing, used by {}", hosts), ERROR_FG_COLOR);
+    }
+    let mut spans = Vec::new();
+    if key.is_orphan() {
+        spans.push(Span::styled("orphan", WARNING_FG_COLOR));
     } else {
-        "j/↓: down | k/↑: up | g/Home: top | G/End: bottom | / or f: search | z: toggle all groups | Enter: login"
-            .to_string()
+        spans.push(Span::raw(key.hosts.join(", ")));
+    }
+    if !key.has_private {
+        spans.push(Span::styled(" (no private key)", ERROR_FG_COLOR));
+    } else if !key.has_public {
+        spans.push(Span::styled(" (no .pub)", DEFAULT_FG_COLOR));
+    }
+    Line::from(spans)
+}
+
+/// Shows the effective settings of the selected host, marking values OpenSSH defaults to.
+fn render_details(frame: &mut Frame, area: Rect, app: &App) {
+    let block = Block::default().borders(Borders::LEFT);
+    let Some(server) = app.server_list.selected() else {
+        frame.render_widget(block, area);
+        return;
     };
-    let footer = Paragraph::new(footer_text);
 
-    frame.render_widget(footer, chunks[1]);
+    let mut lines = Vec::new();
+    if server.is_group {
+        // Including the hosts of its subgroups, a group may only hold subgroups
+        let hosts = app.server_list.subtree_hosts(&server.group).len();
+        lines.push(detail_line("Group", &server.group, None));
+        lines.push(detail_line("Hosts", &hosts.to_string(), None));
+    } else {
+        lines.push(detail_line("Host", &server.host, None));
+        match &server.ip {
+            Some(ip) => lines.push(detail_line("HostName", ip, None)),
+            None => lines.push(detail_line("HostName", &server.host, Some("alias"))),
+        }
+        match (&server.username, server.effective_user()) {
+            (Some(user), _) => lines.push(detail_line("User", user, None)),
+            (None, Some(user)) => lines.push(detail_line("User", &user, Some("local user"))),
+            (None, None) => lines.push(detail_line("User", "", Some("local user"))),
+        }
+        lines.push(detail_line("Port", &server.port.to_string(), None));
+        match app.probe_status(server) {
+            _ if Target::of(server).is_none() => {}
+            Some(ProbeStatus::Up { latency, version }) => {
+                let status = match version {
+                    Some(version) => format!("up, {} ms, {}", latency.as_millis(), version),
+                    None => format!("up, {} ms, no SSH banner", latency.as_millis()),
+                };
+                lines.push(detail_line("Status", &status, None));
+            }
+            Some(ProbeStatus::Down(error)) => {
+                lines.push(detail_line("Status", &format!("down, {}", error), None))
+            }
+            None => lines.push(detail_line("Status", "checking", None)),
+        }
+        let host_key = match (app.host_key_check(server), app.known_status(server)) {
+            (Some(HostKeyCheck::Mismatch { path, line, .. }), _) => {
+                Some(format!("changed, pinned at {}:{}", path.display(), line))
+            }
+            (Some(HostKeyCheck::Revoked { .. }), _) => Some("revoked".to_string()),
+            (_, Some(KnownStatus::Known(types))) => Some(format!("known ({})", types.join(", "))),
+            (_, Some(KnownStatus::CertAuthority)) => Some("signed by a CA".to_string()),
+            (_, Some(KnownStatus::Unknown)) => Some("unknown, ssh will ask".to_string()),
+            (_, None) => None,
+        };
+        if let Some(host_key) = host_key {
+            lines.push(detail_line("HostKey", &host_key, None));
+        }
+        let chain = app.server_list.jump_chain(server);
+        if !chain.is_empty() {
+            let route = format!("{} → {}", chain.join(" → "), server.host);
+            lines.push(detail_line("Route", &route, None));
+        }
+        if let Some(proxy_command) = &server.proxy_command {
+            lines.push(detail_line("Proxy", proxy_command, None));
+        }
+        if server.identity_files.is_empty() {
+            lines.push(detail_line("Identity", "", Some("ssh defaults")));
+        }
+        for identity_file in &server.identity_files {
+            let agent = app.loaded_keys.get(identity_file).map(|&loaded| {
+                if loaded {
+                    "in ssh-agent"
+                } else {
+                    "not in ssh-agent, A: add"
+                }
+            });
+            lines.push(detail_line("Identity", identity_file, agent));
+        }
+        if server.password.is_some() {
+            lines.push(detail_line("Password", "********", None));
+        } else if let Some(password_from) = &server.password_from {
+            lines.push(detail_line(
+                "Password",
+                &format!("from {}", password_from),
+                None,
+            ));
+        }
+        for forward in &server.forwards {
+            lines.push(detail_line("Forward", &forward.to_string(), None));
+        }
+        if let Some(i) = app.tunnels.position(&server.host) {
+            let tunnel = &app.tunnels.tunnels[i];
+            let status = match tunnel.pid() {
+                Some(pid) => format!("running (pid {})", pid),
+                None => "restarting".to_string(),
+            };
+            lines.push(detail_line("Tunnel", &status, None));
+        }
+        if let Some(source) = &server.source {
+            lines.push(detail_line("Source", &source.display().to_string(), None));
+        }
+    }
+
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn detail_line<'a>(label: &'a str, value: &str, default: Option<&'a str>) -> Line<'a> {
+    let mut spans = vec![
+        Span::styled(format!(" {:<10}", label), LABEL_FG_COLOR),
+        Span::styled(value.to_string(), TEXT_FG_COLOR),
+    ];
+    if let Some(default) = default {
+        let separator = if value.is_empty() { "" } else { " " };
+        spans.push(Span::styled(
+            format!("{}({})", separator, default),
+            DEFAULT_FG_COLOR,
+        ));
+    }
+    Line::from(spans)
 }
@@ -0,0 +1,113 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// The effective configuration of a host as computed by `ssh -G`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ResolvedHost {
+    pub host: String,
+    pub hostname: String,
+    pub user: String,
+    pub port: u32,
+    pub identity_files: Vec<String>,
+    pub proxy_jump: Option<String>,
+    pub proxy_command: Option<String>,
+}
+
+/// Resolves `hosts` one after another on a background thread, the receiver yields each result as
+/// soon as it is available. Hosts `ssh -G` fails for are skipped, and the thread stops early when
+/// `ssh` can't be run at all, in both cases the values of the built-in parser are kept.
+pub fn spawn_resolver(config: &Path, hosts: Vec<String>) -> Receiver<ResolvedHost> {
+    let (tx, rx) = mpsc::channel();
+    let config = config.to_path_buf();
+    thread::spawn(move || {
+        for host in hosts {
+            match resolve_host(&config, &host) {
+                Ok(Some(resolved)) => {
+                    if tx.send(resolved).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                // ssh is missing
+                Err(_) => return,
+            }
+        }
+    });
+    rx
+}
+
+pub fn resolve_host(config: &Path, host: &str) -> std::io::Result<Option<ResolvedHost>> {
+    let output = Command::new("ssh")
+        .arg("-G")
+        .arg("-F")
+        .arg(config)
+        .arg(host)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(parse_ssh_g(
+        host,
+        &String::from_utf8_lossy(&output.stdout),
+    )))
+}
+
+fn parse_ssh_g(host: &str, output: &str) -> ResolvedHost {
+    let mut resolved = ResolvedHost {
+        host: host.to_string(),
+        port: 22,
+        ..ResolvedHost::default()
+    };
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+        let value = value.trim().to_string();
+        match key {
+            "hostname" => resolved.hostname = value,
+            "user" => resolved.user = value,
+            "port" => resolved.port = value.parse().unwrap_or(22),
+            "identityfile" => resolved.identity_files.push(value),
+            "proxyjump" if value != "none" => resolved.proxy_jump = Some(value),
+            "proxycommand" if value != "none" => resolved.proxy_command = Some(value),
+            _ => {}
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resolver::{parse_ssh_g, ResolvedHost};
+
+    #[test]
+    fn test_parse_ssh_g() {
+        let output = "host web1\n\
+                      user deploy\n\
+                      hostname 10.0.0.1\n\
+                      port 2222\n\
+                      identityfile ~/.ssh/keys/web\n\
+                      identityfile ~/.ssh/My Keys/id\n\
+                      proxyjump bastion\n\
+                      forwardagent yes\n";
+        assert_eq!(
+            parse_ssh_g("web1", output),
+            ResolvedHost {
+                host: "web1".to_string(),
+                hostname: "10.0.0.1".to_string(),
+                user: "deploy".to_string(),
+                port: 2222,
+                identity_files: vec![
+                    "~/.ssh/keys/web".to_string(),
+                    "~/.ssh/My Keys/id".to_string()
+                ],
+                proxy_jump: Some("bastion".to_string()),
+                proxy_command: None,
+            }
+        );
+    }
+}
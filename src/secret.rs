@@ -0,0 +1,160 @@
+use crate::server::ServerItem;
+use std::io;
+use std::process::{Command, Stdio};
+
+/// A password store lazyssh can fetch host passwords from when connecting, named in the config
+/// with `#: PasswordFrom <provider> [key]`.
+pub trait SecretProvider {
+    /// The name used in `#: PasswordFrom`.
+    fn name(&self) -> &str;
+    /// The secret stored under `key`, the host alias unless the config gives one.
+    fn fetch(&self, key: &str) -> io::Result<String>;
+}
+
+/// The freedesktop Secret Service (GNOME Keyring, KWallet, KeePassXC, ...) through
+/// `secret-tool`, looking up items with the attribute `lazyssh` set to the key.
+pub struct SecretService;
+
+/// The `pass` password store, the key being the name of the entry.
+pub struct Pass;
+
+/// A file encrypted with `gpg`, the key being its path.
+pub struct Gpg;
+
+/// The providers `#: PasswordFrom` can name.
+pub struct SecretProviders(Vec<Box<dyn SecretProvider>>);
+
+impl Default for SecretProviders {
+    fn default() -> Self {
+        Self(vec![Box::new(SecretService), Box::new(Pass), Box::new(Gpg)])
+    }
+}
+
+impl SecretProvider for SecretService {
+    fn name(&self) -> &str {
+        "secret-service"
+    }
+
+    fn fetch(&self, key: &str) -> io::Result<String> {
+        run("secret-tool", &["lookup", "lazyssh", key])
+    }
+}
+
+impl SecretProvider for Pass {
+    fn name(&self) -> &str {
+        "pass"
+    }
+
+    fn fetch(&self, key: &str) -> io::Result<String> {
+        run("pass", &["show", key])
+    }
+}
+
+impl SecretProvider for Gpg {
+    fn name(&self) -> &str {
+        "gpg"
+    }
+
+    fn fetch(&self, key: &str) -> io::Result<String> {
+        let path = match key.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+            None => key.into(),
+        };
+        run("gpg", &["--quiet", "--decrypt", &path.to_string_lossy()])
+    }
+}
+
+/// The first line `program` prints, which is where `pass` and friends put the password.
+fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::other(format!("{} is not installed", program)),
+            _ => e,
+        })?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let secret = stdout.lines().next().unwrap_or_default();
+    if !output.status.success() || secret.is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(match stderr.lines().next() {
+            Some(error) => format!("{}: {}", program, error),
+            None => format!("{} found no secret", program),
+        }));
+    }
+    Ok(secret.to_string())
+}
+
+/// The password to log in to `server` with: its `#: Password`, or the one fetched from the
+/// provider named by its `#: PasswordFrom`.
+pub fn password(server: &ServerItem, providers: &SecretProviders) -> io::Result<Option<String>> {
+    if server.password.is_some() {
+        return Ok(server.password.clone());
+    }
+    let Some(password_from) = &server.password_from else {
+        return Ok(None);
+    };
+    let (name, key) = match password_from.split_once(char::is_whitespace) {
+        Some((name, key)) => (name, key.trim()),
+        None => (password_from.as_str(), ""),
+    };
+    let key = if key.is_empty() { &server.host } else { key };
+    let provider = providers
+        .0
+        .iter()
+        .find(|provider| provider.name() == name)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown password provider `{}`", name),
+            )
+        })?;
+    provider.fetch(key).map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::secret::{password, SecretProvider, SecretProviders};
+    use crate::server::ServerList;
+    use crate::test_util::fixture;
+    use std::collections::HashMap;
+    use std::io;
+
+    /// Stands in for a password store, with the secrets it was given.
+    struct StandIn(HashMap<&'static str, &'static str>);
+
+    impl SecretProvider for StandIn {
+        fn name(&self) -> &str {
+            "stand-in"
+        }
+
+        fn fetch(&self, key: &str) -> io::Result<String> {
+            self.0
+                .get(key)
+                .map(|secret| secret.to_string())
+                .ok_or_else(|| io::Error::other(format!("no secret for {}", key)))
+        }
+    }
+
+    #[test]
+    fn test_password() {
+        let list = ServerList::from_config_file(&fixture("secrets/config"));
+        let providers = SecretProviders(vec![Box::new(StandIn(HashMap::from([
+            ("web1", "by alias"),
+            ("shared/db", "by key"),
+        ])))]);
+        let password = |host: &str| {
+            let server = list.items.iter().find(|i| i.host == host).unwrap();
+            password(server, &providers)
+        };
+
+        assert_eq!(password("web1").unwrap().as_deref(), Some("by alias"));
+        assert_eq!(password("db1").unwrap().as_deref(), Some("by key"));
+        // `#: Password` in the block itself comes first
+        assert_eq!(password("legacy").unwrap().as_deref(), Some("plain"));
+        assert_eq!(password("keyed").unwrap(), None);
+        assert!(password("missing").is_err());
+        assert!(password("unknown").is_err());
+    }
+}
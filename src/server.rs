@@ -1,7 +1,12 @@
+use crate::config_file::ConfigFile;
+use crate::resolver::ResolvedHost;
 use ratatui::widgets::ListState;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 use sublime_fuzzy::best_match;
 use unicode_width::UnicodeWidthStr;
 
@@ -13,111 +18,949 @@ pub struct ServerList {
     pub state: ListState,
     #[serde(skip_serializing)]
     pub expanded_groups: HashMap<String, bool>,
+    /// The top-level config file the list was loaded from.
+    pub config_path: PathBuf,
+    /// Problems found while parsing the config.
+    pub warnings: Vec<ConfigWarning>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ServerItem {
     pub group: String,
     pub is_group: bool,
     pub host: String,
-    pub ip: String,
-    pub username: String,
+    /// `HostName`, when absent OpenSSH connects to the alias itself.
+    pub ip: Option<String>,
+    /// `User`, when absent OpenSSH logs in as the local user.
+    pub username: Option<String>,
     pub port: u32,
-    pub private_key: String,
+    pub private_key: Option<String>,
+    /// Every `IdentityFile` that applies to the host, `private_key` is the first one.
+    pub identity_files: Vec<String>,
+    pub forwards: Vec<Forward>,
+    /// `ProxyJump`, a comma-separated list of `[user@]host[:port]` hops.
+    pub proxy_jump: Option<String>,
+    pub proxy_command: Option<String>,
     pub password: Option<String>,
+    /// `#: PasswordFrom`, the provider to fetch the password from when connecting and its key.
+    pub password_from: Option<String>,
+    /// The config file this entry was read from, `None` for entries synthesized by lazyssh.
+    pub source: Option<PathBuf>,
+    /// 1-based line of the `Host` or `#: Group` line in `source`.
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ForwardKind {
+    Local,
+    Remote,
+    Dynamic,
+}
+
+/// A `LocalForward`, `RemoteForward` or `DynamicForward` directive.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Forward {
+    pub kind: ForwardKind,
+    /// `[bind_address:]port` or a unix socket path connections are accepted on.
+    pub listen: String,
+    /// `host:hostport` connections are forwarded to, `None` for dynamic (SOCKS) forwards.
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigWarning {
+    pub file: PathBuf,
+    /// 1-based, 0 when the problem concerns the whole file.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Forward {
+    /// Parses the arguments of a forward directive, `keyword` being lowercase.
+    fn parse(keyword: &str, args: &str) -> Self {
+        let kind = match keyword {
+            "localforward" => ForwardKind::Local,
+            "remoteforward" => ForwardKind::Remote,
+            _ => ForwardKind::Dynamic,
+        };
+        let mut args = args.split_whitespace();
+        Self {
+            kind,
+            listen: args.next().unwrap_or_default().to_string(),
+            target: args.next().map(str::to_string),
+        }
+    }
+
+    pub fn flag(&self) -> &'static str {
+        match self.kind {
+            ForwardKind::Local => "-L",
+            ForwardKind::Remote => "-R",
+            ForwardKind::Dynamic => "-D",
+        }
+    }
+
+    /// The argument of `flag` on the ssh command line.
+    pub fn cli_spec(&self) -> String {
+        match &self.target {
+            Some(target) => format!("{}:{}", self.listen, target),
+            None => self.listen.clone(),
+        }
+    }
+
+    /// The port opened on this machine, `None` for remote forwards and unix sockets.
+    pub fn local_port(&self) -> Option<u16> {
+        if self.kind == ForwardKind::Remote {
+            return None;
+        }
+        self.split_listen().1.parse().ok()
+    }
+
+    /// Where the forward accepts connections on this machine. Forwards without a bind address
+    /// and wildcard ones are reached on loopback.
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        let port = self.local_port()?;
+        let bind = self.split_listen().0;
+        let bind = bind
+            .strip_prefix('[')
+            .and_then(|bind| bind.strip_suffix(']'))
+            .unwrap_or(bind);
+        let ip = match bind {
+            "" | "*" | "localhost" => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            _ => match bind.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                Ok(IpAddr::V6(ip)) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                Ok(ip) => ip,
+                Err(_) => return (bind, port).to_socket_addrs().ok()?.next(),
+            },
+        };
+        Some(SocketAddr::new(ip, port))
+    }
+
+    /// `[bind_address:]port` split at the last `:`, or `/` as ssh also accepts for IPv6.
+    fn split_listen(&self) -> (&str, &str) {
+        self.listen
+            .rsplit_once([':', '/'])
+            .unwrap_or(("", &self.listen))
+    }
+}
+
+impl fmt::Display for Forward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.kind, &self.target) {
+            (ForwardKind::Local, Some(target)) => write!(f, "L {} → {}", self.listen, target),
+            (ForwardKind::Remote, Some(target)) => write!(f, "R {} ← {}", target, self.listen),
+            (ForwardKind::Remote, None) => write!(f, "R {} (SOCKS)", self.listen),
+            _ => write!(f, "D {} (SOCKS)", self.listen),
+        }
+    }
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+        }
+    }
+}
+
+/// A `Host` or `Match` block, or the global options preceding the first one.
+struct HostBlock {
+    condition: BlockCondition,
+    // Directives in file order, the first value of a keyword wins
+    options: Vec<(String, String)>,
+    password: Option<String>,
+    password_from: Option<String>,
+}
+
+enum BlockCondition {
+    Host(Vec<String>),
+    Match(Vec<MatchCriterion>),
+}
+
+struct MatchCriterion {
+    kind: MatchKind,
+    negated: bool,
+    patterns: String,
+}
+
+enum MatchKind {
+    All,
+    Host,
+    OriginalHost,
+    User,
+    LocalUser,
+    /// `exec`, `localnetwork`, `canonical`, ... depend on the runtime environment, so lazyssh
+    /// never applies blocks using them.
+    Undecidable,
+}
+
+impl HostBlock {
+    fn new(condition: BlockCondition) -> Self {
+        Self {
+            condition,
+            options: Vec::new(),
+            password: None,
+            password_from: None,
+        }
+    }
+
+    /// Whether the block applies to the host `alias`, `hostname` and `user` being the values
+    /// obtained so far which `Match host` and `Match user` are evaluated against.
+    fn applies(&self, alias: &str, hostname: Option<&str>, user: Option<&str>) -> bool {
+        match &self.condition {
+            BlockCondition::Host(patterns) => match_pattern_list(alias, patterns),
+            BlockCondition::Match(criteria) => criteria.iter().all(|criterion| {
+                let matched = match criterion.kind {
+                    MatchKind::All => true,
+                    MatchKind::Host => {
+                        match_comma_list(hostname.unwrap_or(alias), &criterion.patterns)
+                    }
+                    MatchKind::OriginalHost => match_comma_list(alias, &criterion.patterns),
+                    MatchKind::User => match user.map(str::to_string).or_else(local_user) {
+                        Some(user) => match_comma_list(&user, &criterion.patterns),
+                        None => false,
+                    },
+                    MatchKind::LocalUser => match local_user() {
+                        Some(user) => match_comma_list(&user, &criterion.patterns),
+                        None => false,
+                    },
+                    MatchKind::Undecidable => return false,
+                };
+                matched != criterion.negated
+            }),
+        }
+    }
+}
+
+impl MatchCriterion {
+    /// Parses the arguments of a `Match` line, e.g. `host *.prod !user root`.
+    fn parse_all(args: &[&str]) -> Vec<Self> {
+        let mut criteria = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (negated, keyword) = match arg.strip_prefix('!') {
+                Some(keyword) => (true, keyword.to_lowercase()),
+                None => (false, arg.to_lowercase()),
+            };
+            let kind = match keyword.as_str() {
+                "all" => MatchKind::All,
+                "canonical" | "final" => MatchKind::Undecidable,
+                "host" => MatchKind::Host,
+                "originalhost" => MatchKind::OriginalHost,
+                "user" => MatchKind::User,
+                "localuser" => MatchKind::LocalUser,
+                // Everything else takes an argument we can't evaluate statically
+                _ => {
+                    args.next();
+                    MatchKind::Undecidable
+                }
+            };
+            let patterns = match kind {
+                MatchKind::All | MatchKind::Undecidable => String::new(),
+                _ => args.next().map(|arg| arg.to_string()).unwrap_or_default(),
+            };
+            criteria.push(MatchCriterion {
+                kind,
+                negated,
+                patterns,
+            });
+        }
+        criteria
+    }
+}
+
+/// A concrete host as it appears in the config, resolved into a `ServerItem` once parsing is done.
+struct HostEntry {
+    host: String,
+    group: String,
+    source: Option<PathBuf>,
+    line: usize,
+    block: usize,
+}
+
+enum ConfigEntry {
+    Group(Box<ServerItem>),
+    Host(HostEntry),
 }
 
-#[derive(Default)]
 struct SshConfigParser {
+    ssh_dir: PathBuf,
+    // Canonical paths of the files currently being read, used to detect include cycles
+    include_stack: Vec<PathBuf>,
+    current_file: Option<PathBuf>,
+    current_line: usize,
     current_group: Option<String>,
-    current_is_group: Option<bool>,
-    current_host: Option<String>,
-    current_ip: Option<String>,
-    current_user: Option<String>,
-    current_port: u32,
-    current_private_key: Option<String>,
-    current_password: Option<String>,
-    items: Vec<ServerItem>,
+    current_block: usize,
+    blocks: Vec<HostBlock>,
+    entries: Vec<ConfigEntry>,
+    // Where each alias was first defined, to report duplicates
+    defined_hosts: HashMap<String, (PathBuf, usize)>,
+    // Patterns of `IgnoreUnknown`, matching keywords aren't reported as unknown
+    ignore_unknown: Vec<String>,
+    warnings: Vec<ConfigWarning>,
 }
 
-const OTHER_GROUP: &str = "other";
+/// The group of hosts preceding the first `#: Group` marker.
+pub const OTHER_GROUP: &str = "other";
+/// Separates the levels of nested groups, as in `#: Group Prod/EU/Web`.
+pub const GROUP_SEPARATOR: char = '/';
+// Same limit as OpenSSH's READCONF_MAX_DEPTH
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// Every keyword `ssh_config(5)` knows about, including deprecated ones and Apple's `UseKeychain`.
+const KNOWN_KEYWORDS: &[&str] = &[
+    "addkeystoagent",
+    "addressfamily",
+    "batchmode",
+    "bindaddress",
+    "bindinterface",
+    "canonicaldomains",
+    "canonicalizefallbacklocal",
+    "canonicalizehostname",
+    "canonicalizemaxdots",
+    "canonicalizepermittedcnames",
+    "casignaturealgorithms",
+    "certificatefile",
+    "challengeresponseauthentication",
+    "channeltimeout",
+    "checkhostip",
+    "cipher",
+    "ciphers",
+    "clearallforwardings",
+    "compression",
+    "compressionlevel",
+    "connectionattempts",
+    "connecttimeout",
+    "controlmaster",
+    "controlpath",
+    "controlpersist",
+    "dynamicforward",
+    "enableescapecommandline",
+    "enablesshkeysign",
+    "escapechar",
+    "exitonforwardfailure",
+    "fingerprinthash",
+    "forkafterauthentication",
+    "forwardagent",
+    "forwardx11",
+    "forwardx11timeout",
+    "forwardx11trusted",
+    "gatewayports",
+    "globalknownhostsfile",
+    "gssapiauthentication",
+    "gssapidelegatecredentials",
+    "hashknownhosts",
+    "host",
+    "hostbasedacceptedalgorithms",
+    "hostbasedauthentication",
+    "hostbasedkeytypes",
+    "hostkeyalgorithms",
+    "hostkeyalias",
+    "hostname",
+    "identitiesonly",
+    "identityagent",
+    "identityfile",
+    "ignoreunknown",
+    "include",
+    "ipqos",
+    "kbdinteractiveauthentication",
+    "kbdinteractivedevices",
+    "kexalgorithms",
+    "knownhostscommand",
+    "localcommand",
+    "localforward",
+    "loglevel",
+    "logverbose",
+    "macs",
+    "match",
+    "nohostauthenticationforlocalhost",
+    "numberofpasswordprompts",
+    "obscurekeystroketiming",
+    "passwordauthentication",
+    "permitlocalcommand",
+    "permitremoteopen",
+    "pkcs11provider",
+    "port",
+    "preferredauthentications",
+    "protocol",
+    "proxycommand",
+    "proxyjump",
+    "proxyusefdpass",
+    "pubkeyacceptedalgorithms",
+    "pubkeyacceptedkeytypes",
+    "pubkeyauthentication",
+    "refuseconnection",
+    "rekeylimit",
+    "remotecommand",
+    "remoteforward",
+    "requesttty",
+    "requiredrsasize",
+    "revokedhostkeys",
+    "rsaauthentication",
+    "securitykeyprovider",
+    "sendenv",
+    "serveralivecountmax",
+    "serveraliveinterval",
+    "sessiontype",
+    "setenv",
+    "stdinnull",
+    "streamlocalbindmask",
+    "streamlocalbindunlink",
+    "stricthostkeychecking",
+    "syslogfacility",
+    "tag",
+    "tcpkeepalive",
+    "tunnel",
+    "tunneldevice",
+    "updatehostkeys",
+    "usekeychain",
+    "user",
+    "userknownhostsfile",
+    "verifyhostkeydns",
+    "versionaddendum",
+    "visualhostkey",
+    "warnweakcrypto",
+    "xauthlocation",
+];
 
 impl SshConfigParser {
-    fn new() -> Self {
-        Self::default()
+    /// `ssh_dir` is the directory relative `Include` paths are resolved against.
+    fn new(ssh_dir: &Path) -> Self {
+        Self {
+            ssh_dir: ssh_dir.to_path_buf(),
+            include_stack: Vec::new(),
+            current_file: None,
+            current_line: 0,
+            current_group: None,
+            current_block: 0,
+            // Options before the first `Host` line apply to every host
+            blocks: vec![HostBlock::new(BlockCondition::Host(vec!["*".to_string()]))],
+            entries: Vec::new(),
+            defined_hosts: HashMap::new(),
+            ignore_unknown: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Records a problem at the line being parsed, or for `path` itself when it is the
+    /// top-level file.
+    fn warn(&mut self, path: &Path, message: String) {
+        let (file, line) = match &self.current_file {
+            Some(file) => (file.clone(), self.current_line),
+            None => (path.to_path_buf(), 0),
+        };
+        self.warnings.push(ConfigWarning {
+            file,
+            line,
+            message,
+        });
+    }
+
+    fn parse_file(&mut self, path: &Path) {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.include_stack.contains(&canonical) {
+            self.warn(path, format!("`{}` includes itself", path.display()));
+            return;
+        }
+        if self.include_stack.len() >= MAX_INCLUDE_DEPTH {
+            self.warn(path, "too many nested includes".to_string());
+            return;
+        }
+        let file = match ConfigFile::load(path) {
+            Ok(file) => file,
+            Err(e) => {
+                self.warn(path, format!("cannot read `{}`: {}", path.display(), e));
+                return;
+            }
+        };
+
+        self.include_stack.push(canonical);
+        let parent_file = self.current_file.replace(path.to_path_buf());
+        let parent_line = self.current_line;
+        for (i, line) in file.lines.iter().enumerate() {
+            self.current_line = i + 1;
+            self.parse_line(line);
+        }
+        self.current_file = parent_file;
+        self.current_line = parent_line;
+        self.include_stack.pop();
+    }
+
+    fn include(&mut self, patterns: &[&str]) {
+        // Like OpenSSH, the including block is active again once the included files are done
+        let including_block = self.current_block;
+        for pattern in patterns {
+            let path = self.resolve_include_path(pattern);
+            let path_str = path.to_string_lossy();
+            if path_str.contains(['*', '?', '[']) {
+                // glob yields matches in alphabetical order, which is what OpenSSH does too
+                let Ok(paths) = glob::glob(&path_str) else {
+                    continue;
+                };
+                for path in paths.flatten() {
+                    self.parse_file(&path);
+                }
+            } else {
+                self.parse_file(&path);
+            }
+        }
+        self.current_block = including_block;
+    }
+
+    fn resolve_include_path(&self, pattern: &str) -> PathBuf {
+        if let Some(rest) = pattern.strip_prefix("~/") {
+            if let Some(home) = dirs::home_dir() {
+                return home.join(rest);
+            }
+        }
+        let path = Path::new(pattern);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.ssh_dir.join(path)
+        }
     }
 
     fn parse_line(&mut self, line: &str) {
         let line = line.trim();
-        if line.starts_with("#: Group") {
-            self.flush_current_host();
-            let group_name = line[8..].trim().to_string();
-            self.items.push(ServerItem {
+        if let Some(group_name) = line.strip_prefix("#: Group") {
+            let group_name = group_path(group_name);
+            if is_reserved_group(&group_name) {
+                self.warn_here(format!(
+                    "group name `{}` is reserved, its hosts are listed without group",
+                    group_name
+                ));
+                self.current_group = None;
+                return;
+            }
+            self.entries.push(ConfigEntry::Group(Box::new(ServerItem {
                 group: group_name.clone(),
                 is_group: true,
                 host: String::new(),
-                ip: String::new(),
-                username: String::new(),
+                ip: None,
+                username: None,
                 port: 0,
-                private_key: String::new(),
+                private_key: None,
+                identity_files: Vec::new(),
+                forwards: Vec::new(),
+                proxy_jump: None,
+                proxy_command: None,
                 password: None,
-            });
+                password_from: None,
+                source: self.current_file.clone(),
+                line: self.current_line,
+            })));
             self.current_group = Some(group_name);
-            self.current_is_group = Some(true);
             return;
         }
 
-        if line.starts_with("#: Password") {
-            let password = line[11..].trim().to_string();
-            self.current_password = Some(password);
+        // Before `#: Password`, which it starts with
+        if let Some(password_from) = line.strip_prefix("#: PasswordFrom") {
+            self.blocks[self.current_block].password_from = Some(password_from.trim().to_string());
             return;
         }
 
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 2 {
+        if let Some(password) = line.strip_prefix("#: Password") {
+            self.blocks[self.current_block].password = Some(password.trim().to_string());
             return;
         }
 
-        match parts[0] {
-            "Host" => {
-                self.flush_current_host();
-                if parts[1] != "*" {
-                    let host_name = parts[1..].join(" ");
-                    self.current_host = Some(host_name);
-                    self.current_is_group = Some(false);
-                    self.reset_current_values();
-                }
+        if line == "#: SpacedName" {
+            self.use_spaced_name();
+            return;
+        }
+
+        let tokens = tokenize(line);
+        if tokens.is_empty() {
+            return;
+        }
+        let keyword = tokens[0].to_lowercase();
+        if !KNOWN_KEYWORDS.contains(&keyword.as_str()) {
+            if !match_pattern_list(&keyword, &self.ignore_unknown) {
+                self.warn_here(format!("unknown keyword `{}`", tokens[0]));
+            }
+            return;
+        }
+        if tokens.len() < 2 {
+            self.warn_here(format!("missing argument for `{}`", tokens[0]));
+            return;
+        }
+        let args: Vec<&str> = tokens[1..].iter().map(String::as_str).collect();
+
+        match keyword.as_str() {
+            "host" => self.start_host_block(&args),
+            "match" => {
+                let criteria = MatchCriterion::parse_all(&args);
+                self.blocks
+                    .push(HostBlock::new(BlockCondition::Match(criteria)));
+                self.current_block = self.blocks.len() - 1;
+            }
+            "include" => self.include(&args),
+            "ignoreunknown" => self
+                .ignore_unknown
+                .extend(args[0].split(',').map(str::to_lowercase)),
+            "port" if !matches!(args[0].parse::<u16>(), Ok(port) if port > 0) => {
+                self.warn_here(format!("invalid port `{}`", args[0]));
+            }
+            "localforward" if args.len() < 2 => {
+                self.warn_here(format!("missing target for `{}`", tokens[0]));
+            }
+            "localforward" | "remoteforward" | "dynamicforward" => self.blocks[self.current_block]
+                .options
+                .push((keyword, args.join(" "))),
+            "hostname" | "user" | "port" | "identityfile" | "proxyjump" => self.blocks
+                [self.current_block]
+                .options
+                .push((keyword, args[0].to_string())),
+            // The command is passed to the shell as written
+            "proxycommand" => {
+                let command = split_keyword(line).1.trim_end().to_string();
+                self.blocks[self.current_block]
+                    .options
+                    .push((keyword, command));
+            }
+            _ => {}
+        }
+    }
+
+    fn warn_here(&mut self, message: String) {
+        self.warnings.push(ConfigWarning {
+            file: self.current_file.clone().unwrap_or_default(),
+            line: self.current_line,
+            message,
+        });
+    }
+
+    fn start_host_block(&mut self, patterns: &[&str]) {
+        self.blocks.push(HostBlock::new(BlockCondition::Host(
+            patterns.iter().map(|p| p.to_string()).collect(),
+        )));
+        self.current_block = self.blocks.len() - 1;
+
+        // Every concrete alias is a host sharing the block, wildcard patterns such as `*` or
+        // `*.prod` and negated ones only provide defaults
+        for pattern in patterns {
+            if is_wildcard(pattern) || pattern.starts_with('!') {
+                continue;
+            }
+            self.add_host(pattern, self.current_line);
+        }
+    }
+
+    /// Lists the host `name` of the current block, whose `Host` line is `line`.
+    fn add_host(&mut self, name: &str, line: usize) {
+        let file = self.current_file.clone().unwrap_or_default();
+        match self.defined_hosts.get(&name.to_lowercase()) {
+            Some((first_file, first_line)) => {
+                let message = format!(
+                    "duplicate host `{}`, first defined at {}:{}",
+                    name,
+                    first_file.display(),
+                    first_line
+                );
+                self.warnings.push(ConfigWarning {
+                    file: file.clone(),
+                    line,
+                    message,
+                });
+            }
+            None => {
+                self.defined_hosts.insert(name.to_lowercase(), (file, line));
+            }
+        }
+        self.entries.push(ConfigEntry::Host(HostEntry {
+            host: name.to_string(),
+            group: self
+                .current_group
+                .clone()
+                .unwrap_or_else(|| OTHER_GROUP.to_string()),
+            source: self.current_file.clone(),
+            line,
+            block: self.current_block,
+        }));
+    }
+
+    /// Reads the `Host` line of the current block as one legacy host name containing spaces,
+    /// replacing the hosts `start_host_block` listed for each of its words. The block then only
+    /// applies to that name.
+    fn use_spaced_name(&mut self) {
+        let block = self.current_block;
+        let BlockCondition::Host(patterns) = &self.blocks[block].condition else {
+            return;
+        };
+        let name = patterns.join(" ");
+        let words: Vec<String> = patterns.iter().map(|word| word.to_lowercase()).collect();
+        let Some(line) = self.entries.iter().find_map(|entry| match entry {
+            ConfigEntry::Host(host) if host.block == block => Some(host.line),
+            _ => None,
+        }) else {
+            return;
+        };
+
+        let file = self.current_file.clone().unwrap_or_default();
+        for word in &words {
+            if self.defined_hosts.get(word) == Some(&(file.clone(), line)) {
+                self.defined_hosts.remove(word);
+            }
+        }
+        self.warnings.retain(|warning| {
+            !(warning.file == file
+                && warning.line == line
+                && warning.message.starts_with("duplicate host"))
+        });
+        self.entries
+            .retain(|entry| !matches!(entry, ConfigEntry::Host(host) if host.block == block));
+        self.blocks[block].condition = BlockCondition::Host(vec![name.clone()]);
+        self.add_host(&name, line);
+    }
+
+    /// Computes the effective settings of every host, the first value obtained for each keyword
+    /// across all matching blocks wins, like OpenSSH does.
+    fn finish(mut self) -> (Vec<ServerItem>, Vec<ConfigWarning>) {
+        let entries = std::mem::take(&mut self.entries);
+        let mut items = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match entry {
+                ConfigEntry::Group(item) => items.push(*item),
+                ConfigEntry::Host(entry) => items.push(self.resolve(entry)),
+            }
+        }
+        (items, self.warnings)
+    }
+
+    fn resolve(&mut self, entry: HostEntry) -> ServerItem {
+        let mut ip = None;
+        let mut user = None;
+        let mut port = None;
+        let mut identity_files = Vec::new();
+        let mut forwards = Vec::new();
+        let mut proxy_jump = None;
+        let mut proxy_command = None;
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i != entry.block && !block.applies(&entry.host, ip.as_deref(), user.as_deref()) {
+                continue;
+            }
+            for (keyword, value) in &block.options {
+                match keyword.as_str() {
+                    "hostname" => ip.get_or_insert_with(|| value.clone()),
+                    "user" => user.get_or_insert_with(|| value.clone()),
+                    "port" => port.get_or_insert_with(|| value.clone()),
+                    // Whichever of ProxyJump and ProxyCommand comes first wins
+                    "proxyjump" if proxy_command.is_none() => {
+                        proxy_jump.get_or_insert_with(|| value.clone())
+                    }
+                    "proxycommand" if proxy_jump.is_none() => {
+                        proxy_command.get_or_insert_with(|| value.clone())
+                    }
+                    // Identity files accumulate instead of the first one winning
+                    "identityfile" => {
+                        identity_files.push(value.clone());
+                        continue;
+                    }
+                    // So do forwards
+                    "localforward" | "remoteforward" | "dynamicforward" => {
+                        forwards.push(Forward::parse(keyword, value));
+                        continue;
+                    }
+                    _ => continue,
+                };
             }
-            "HostName" => self.current_ip = Some(parts[1].to_string()),
-            "User" => self.current_user = Some(parts[1].to_string()),
-            "Port" => self.current_port = parts[1].parse().unwrap_or(22),
-            "IdentityFile" => self.current_private_key = Some(parts[1].to_string()),
+        }
+
+        ServerItem {
+            group: entry.group,
+            is_group: false,
+            ip,
+            username: user,
+            port: port.and_then(|port| port.parse().ok()).unwrap_or(22),
+            private_key: identity_files.first().cloned(),
+            identity_files,
+            forwards,
+            // `none` takes the place of the first value, disabling any later one
+            proxy_jump: proxy_jump.filter(|jump| jump != "none"),
+            proxy_command: proxy_command.filter(|command| command != "none"),
+            password: self.blocks[entry.block].password.clone(),
+            password_from: self.blocks[entry.block].password_from.clone(),
+            source: entry.source,
+            line: entry.line,
+            host: entry.host,
+        }
+    }
+}
+
+/// Splits a config line into its keyword and arguments following OpenSSH's rules: the keyword
+/// may be separated from its arguments by whitespace and/or one `=`, arguments may be quoted with
+/// `"` or `'` and a `#` starting an argument comments out the rest of the line.
+pub fn tokenize(line: &str) -> Vec<String> {
+    let line = line.trim_start();
+    if line.is_empty() || line.starts_with('#') {
+        return Vec::new();
+    }
+
+    let (keyword, rest) = split_keyword(line);
+    let mut tokens = vec![keyword.to_string()];
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None | Some('#') => break,
             _ => {}
         }
+
+        let mut token = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (None, c) if c.is_whitespace() => break,
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), c) if c == q => quote = None,
+                (_, '\\') => match chars.peek() {
+                    Some(&next) if matches!(next, '\\' | '"' | '\'') || next.is_whitespace() => {
+                        token.push(next);
+                        chars.next();
+                    }
+                    _ => token.push(c),
+                },
+                (_, c) => token.push(c),
+            }
+        }
+        tokens.push(token);
     }
+    tokens
+}
+
+/// Splits a trimmed config line into its keyword and the raw text of its arguments.
+pub fn split_keyword(line: &str) -> (&str, &str) {
+    let keyword_end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let rest = line[keyword_end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+    (&line[..keyword_end], rest)
+}
 
-    fn flush_current_host(&mut self) {
-        if let Some(host) = self.current_host.take() {
-            self.items.push(ServerItem::new(
-                self.current_group.as_deref().unwrap_or(OTHER_GROUP),
-                self.current_is_group.unwrap_or(false),
-                &host,
-                self.current_ip.as_deref().unwrap_or("unknown"),
-                self.current_user.as_deref().unwrap_or("jing"),
-                self.current_port,
-                self.current_private_key.as_deref().unwrap_or("unknown"),
-                self.current_password.take(),
-            ));
+/// The host part of a `ProxyJump` hop, e.g. `bastion` for `ssh://deploy@bastion:2222`.
+fn jump_host(hop: &str) -> &str {
+    let hop = hop.trim();
+    let hop = hop.strip_prefix("ssh://").unwrap_or(hop);
+    let hop = hop.rsplit_once('@').map_or(hop, |(_, host)| host);
+    if let Some(bracketed) = hop.strip_prefix('[') {
+        return bracketed.split(']').next().unwrap_or(bracketed);
+    }
+    hop.split(':').next().unwrap_or(hop)
+}
+
+fn local_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .ok()
+}
+
+/// OpenSSH semantics: any positive pattern has to match and no negated one may match.
+fn match_pattern_list<S: AsRef<str>>(value: &str, patterns: &[S]) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        if let Some(negated) = pattern.strip_prefix('!') {
+            if match_pattern(value, negated) {
+                return false;
+            }
+        } else if match_pattern(value, pattern) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Same as `match_pattern_list` for the comma-separated lists used by `Match`.
+pub fn match_comma_list(value: &str, patterns: &str) -> bool {
+    match_pattern_list(value, &patterns.split(',').collect::<Vec<_>>())
+}
+
+/// Normalizes the name of a `#: Group` marker, `Prod / EU` and `Prod/EU/` both being `Prod/EU`.
+pub fn group_path(name: &str) -> String {
+    let levels: Vec<&str> = name
+        .split(GROUP_SEPARATOR)
+        .map(str::trim)
+        .filter(|level| !level.is_empty())
+        .collect();
+    levels.join(&GROUP_SEPARATOR.to_string())
+}
+
+/// Whether `group` is, or is nested in, the `other` group hosts without group are listed under.
+pub fn is_reserved_group(group: &str) -> bool {
+    group.split(GROUP_SEPARATOR).next() == Some(OTHER_GROUP)
+}
+
+/// The group `group` is nested in, empty for top-level groups.
+pub fn parent_group(group: &str) -> &str {
+    group
+        .rsplit_once(GROUP_SEPARATOR)
+        .map_or("", |(parent, _)| parent)
+}
+
+/// Whether `group` is `ancestor` or nested in it at any depth.
+pub fn is_in_group(group: &str, ancestor: &str) -> bool {
+    group == ancestor
+        || group
+            .strip_prefix(ancestor)
+            .is_some_and(|rest| rest.starts_with(GROUP_SEPARATOR))
+}
+
+/// Adds an entry for every group that only exists as the parent of nested ones, such as `Prod`
+/// for `#: Group Prod/EU`.
+fn add_parent_groups(items: &mut Vec<ServerItem>) {
+    let mut i = 0;
+    while i < items.len() {
+        if items[i].is_group {
+            let mut parent = parent_group(&items[i].group).to_string();
+            while !parent.is_empty() {
+                if !items
+                    .iter()
+                    .any(|item| item.is_group && item.group == parent)
+                {
+                    let mut item = items[i].clone();
+                    item.group = parent.clone();
+                    item.source = None;
+                    item.line = 0;
+                    items.insert(i, item);
+                    i += 1;
+                }
+                parent = parent_group(&parent).to_string();
+            }
         }
+        i += 1;
     }
+}
+
+pub fn is_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
 
-    fn reset_current_values(&mut self) {
-        self.current_ip = None;
-        self.current_user = None;
-        self.current_port = 22;
-        self.current_private_key = None;
-        self.current_password = None;
+/// Case-insensitive glob match supporting `*` and `?`, as used by `Host` patterns.
+fn match_pattern(host: &str, pattern: &str) -> bool {
+    let host: Vec<char> = host.to_lowercase().chars().collect();
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let (mut h, mut p) = (0, 0);
+    // Position of the last `*` in the pattern and the host position it was tried at
+    let mut backtrack = None;
+    while h < host.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == host[h]) {
+            h += 1;
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, h));
+            p += 1;
+        } else if let Some((star, tried)) = backtrack {
+            p = star + 1;
+            h = tried + 1;
+            backtrack = Some((star, h));
+        } else {
+            return false;
+        }
     }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 impl ServerList {
@@ -125,38 +968,170 @@ impl ServerList {
         let path = dirs::home_dir()
             .map(|p| p.join(".ssh/config"))
             .unwrap_or_else(|| "/dev/null".into());
+        Self::from_config_file(&path)
+    }
 
-        let content = fs::read_to_string(path).unwrap_or_default();
-        let mut parser = SshConfigParser::new();
-
-        // Parse all lines
-        content.lines().for_each(|line| parser.parse_line(line));
+    /// Loads the servers from `path` and every file it includes. Relative `Include` paths are
+    /// resolved against the directory of `path`, which is `~/.ssh` for the default config.
+    pub fn from_config_file(path: &Path) -> Self {
+        let ssh_dir = path.parent().unwrap_or(Path::new("/"));
+        let mut parser = SshConfigParser::new(ssh_dir);
 
-        // Flush the last host if exists
-        parser.flush_current_host();
+        // Parse the config and all included files
+        parser.parse_file(path);
+        let (mut items, warnings) = parser.finish();
+        add_parent_groups(&mut items);
 
         let mut state = ListState::default();
         state.select(Some(0));
 
         let mut expanded_groups = HashMap::new();
         // Initialize all groups as expanded by default
-        for item in &parser.items {
+        for item in &items {
             if item.is_group {
                 expanded_groups.insert(item.group.clone(), true);
             }
         }
 
         let mut result = Self {
-            items: parser.items,
+            items,
             state,
             filtered_items: Vec::new(),
             expanded_groups,
+            config_path: path.to_path_buf(),
+            warnings,
         };
 
         result.reset_filter();
         result
     }
 
+    /// Reads the config again after it was edited, keeping collapsed groups collapsed and the
+    /// same row selected.
+    pub fn reload(&mut self) {
+        let mut reloaded = Self::from_config_file(&self.config_path);
+        for (group, expanded) in &self.expanded_groups {
+            if let Some(is_expanded) = reloaded.expanded_groups.get_mut(group) {
+                *is_expanded = *expanded;
+            }
+        }
+        let previous = self.state.selected().unwrap_or(0);
+        *self = reloaded;
+        let last = self.visible_items().len().saturating_sub(1);
+        self.state.select(Some(previous.min(last)));
+    }
+
+    pub fn select_host(&mut self, host: &str) {
+        let index = self
+            .visible_items()
+            .iter()
+            .position(|item| !item.is_group && item.host == host);
+        if index.is_some() {
+            self.state.select(index);
+        }
+    }
+
+    pub fn select_group(&mut self, group: &str) {
+        let index = self
+            .visible_items()
+            .iter()
+            .position(|item| item.is_group && item.group == group);
+        if index.is_some() {
+            self.state.select(index);
+        }
+    }
+
+    /// The `#: Group` markers of `group`, a group can be split across several.
+    pub fn group_markers(&self, group: &str) -> Vec<&ServerItem> {
+        self.items
+            .iter()
+            .filter(|item| item.is_group && item.group == group)
+            .collect()
+    }
+
+    /// The markers of `group` and of the groups nested in it.
+    pub fn subtree_markers(&self, group: &str) -> Vec<&ServerItem> {
+        self.items
+            .iter()
+            .filter(|item| item.is_group && is_in_group(&item.group, group))
+            .collect()
+    }
+
+    /// The hosts of `group` and of the groups nested in it.
+    pub fn subtree_hosts(&self, group: &str) -> Vec<&ServerItem> {
+        self.items
+            .iter()
+            .filter(|item| !item.is_group && is_in_group(&item.group, group))
+            .collect()
+    }
+
+    /// Aliases of the hosts `ssh -G` can resolve.
+    pub fn ssh_aliases(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .filter(|item| !item.is_group && item.has_ssh_alias())
+            .map(|item| item.host.clone())
+            .collect()
+    }
+
+    /// Replaces the settings computed by the built-in parser with the ones reported by `ssh -G`.
+    pub fn apply_resolved(&mut self, resolved: ResolvedHost) {
+        for item in self
+            .items
+            .iter_mut()
+            .filter(|item| !item.is_group && item.host == resolved.host)
+        {
+            item.ip = Some(resolved.hostname.clone());
+            item.username = Some(resolved.user.clone());
+            item.port = resolved.port;
+            item.private_key = resolved.identity_files.first().cloned();
+            item.identity_files = resolved.identity_files.clone();
+            item.proxy_jump = resolved.proxy_jump.clone();
+            item.proxy_command = resolved.proxy_command.clone();
+        }
+    }
+
+    /// The hops to go through to reach `server`, first hop first. Hops that are themselves hosts
+    /// of the list are labelled with their HostName and preceded by their own jumps.
+    pub fn jump_chain(&self, server: &ServerItem) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut visited = vec![server.host.clone()];
+        self.collect_jumps(server, &mut chain, &mut visited);
+        chain
+    }
+
+    fn collect_jumps(
+        &self,
+        server: &ServerItem,
+        chain: &mut Vec<String>,
+        visited: &mut Vec<String>,
+    ) {
+        if server.proxy_command.is_some() && server.proxy_jump.is_none() {
+            chain.push("ProxyCommand".to_string());
+            return;
+        }
+        let Some(proxy_jump) = &server.proxy_jump else {
+            return;
+        };
+        for hop in proxy_jump.split(',') {
+            let host = jump_host(hop);
+            let jump = self
+                .items
+                .iter()
+                .find(|item| !item.is_group && item.host == host);
+            match jump {
+                Some(jump) if !visited.contains(&jump.host) => {
+                    visited.push(jump.host.clone());
+                    self.collect_jumps(jump, chain, visited);
+                    chain.push(format!("{} ({})", jump.host, jump.hostname()));
+                }
+                // A loop, OpenSSH would fail to connect
+                Some(jump) => chain.push(format!("{} (loop)", jump.host)),
+                None => chain.push(hop.to_string()),
+            }
+        }
+    }
+
     pub fn filter_items(&mut self, query: &str) {
         if query.is_empty() {
             self.reset_filter();
@@ -184,18 +1159,22 @@ impl ServerList {
         self.state.select(Some(0));
     }
 
+    /// Collapses or expands the selected group along with the groups nested in it.
     pub fn toggle_group(&mut self) {
         if let Some(selected) = self.selected() {
             if selected.is_group {
                 let group_name = selected.group.clone();
                 let current_index = self.state.selected().unwrap_or(0);
-                if let Some(is_expanded) = self.expanded_groups.get_mut(&group_name) {
-                    *is_expanded = !*is_expanded;
-                    // Rebuild filtered items without resetting selection
-                    self.filtered_items = (0..self.items.len()).collect();
-                    // Restore the selection
-                    self.state.select(Some(current_index));
+                let expanded = !self.is_group_expanded(&group_name);
+                for (group, is_expanded) in self.expanded_groups.iter_mut() {
+                    if is_in_group(group, &group_name) {
+                        *is_expanded = expanded;
+                    }
                 }
+                // Rebuild filtered items without resetting selection
+                self.filtered_items = (0..self.items.len()).collect();
+                // Restore the selection
+                self.state.select(Some(current_index));
             }
         }
     }
@@ -204,24 +1183,98 @@ impl ServerList {
         self.expanded_groups.get(group).copied().unwrap_or(true)
     }
 
+    /// The rows of the tree: hosts without group first, then each group followed by its hosts
+    /// and its subgroups, leaving out what collapsed groups contain.
     pub fn visible_items(&self) -> Vec<&ServerItem> {
-        let mut visible = Vec::new();
-        let mut current_group = None;
-        let mut current_group_expanded = true;
-
+        let mut filtered = vec![false; self.items.len()];
         for &idx in &self.filtered_items {
+            filtered[idx] = true;
+        }
+
+        let mut visible = Vec::new();
+        // Depth of the collapsed group whose subtree is being skipped
+        let mut hidden_below = None;
+        for idx in self.tree_order() {
             let item = &self.items[idx];
-            if item.is_group {
-                current_group = Some(&item.group);
-                current_group_expanded = self.is_group_expanded(&item.group);
-                visible.push(item);
-            } else if current_group_expanded {
-                visible.push(item);
+            let depth = item.depth();
+            match hidden_below {
+                Some(hidden) if depth > hidden => continue,
+                _ => hidden_below = None,
+            }
+            if !filtered[idx] {
+                continue;
+            }
+            visible.push(item);
+            if item.is_group && !self.is_group_expanded(&item.group) {
+                hidden_below = Some(depth);
             }
         }
         visible
     }
 
+    /// Indices of `items` in tree order. Groups are ordered by their first appearance in the
+    /// config, a group marked several times is listed once.
+    fn tree_order(&self) -> Vec<usize> {
+        let mut groups: Vec<(&str, usize)> = Vec::new();
+        for item in self.items.iter().filter(|item| item.is_group) {
+            // A nested group also makes its parents appear
+            let mut ancestors = vec![item.group.as_str()];
+            let mut parent = parent_group(&item.group);
+            while !parent.is_empty() {
+                ancestors.insert(0, parent);
+                parent = parent_group(parent);
+            }
+            for group in ancestors {
+                if groups.iter().any(|(seen, _)| *seen == group) {
+                    continue;
+                }
+                let idx = self
+                    .items
+                    .iter()
+                    .position(|item| item.is_group && item.group == group);
+                groups.extend(idx.map(|idx| (group, idx)));
+            }
+        }
+
+        let mut order: Vec<usize> = (0..self.items.len())
+            .filter(|&idx| !self.items[idx].is_group && self.items[idx].group == OTHER_GROUP)
+            .collect();
+        self.push_subtree("", &groups, &mut order);
+        order
+    }
+
+    fn push_subtree(&self, parent: &str, groups: &[(&str, usize)], order: &mut Vec<usize>) {
+        for &(group, idx) in groups {
+            if parent_group(group) != parent {
+                continue;
+            }
+            order.push(idx);
+            order.extend(
+                (0..self.items.len())
+                    .filter(|&idx| !self.items[idx].is_group && self.items[idx].group == group),
+            );
+            self.push_subtree(group, groups, order);
+        }
+    }
+
+    /// The next group nested in the same parent, or the previous one if `down` is false.
+    pub fn sibling_group(&self, group: &str, down: bool) -> Option<String> {
+        let siblings: Vec<&str> = self
+            .tree_order()
+            .into_iter()
+            .map(|idx| &self.items[idx])
+            .filter(|item| item.is_group && parent_group(&item.group) == parent_group(group))
+            .map(|item| item.group.as_str())
+            .collect();
+        let i = siblings.iter().position(|sibling| *sibling == group)?;
+        let sibling = if down {
+            siblings.get(i + 1)
+        } else {
+            i.checked_sub(1).and_then(|i| siblings.get(i))
+        };
+        sibling.map(|sibling| sibling.to_string())
+    }
+
     pub fn select_next(&mut self) {
         self.state.select_next();
     }
@@ -238,19 +1291,10 @@ impl ServerList {
         self.state.select_last();
     }
 
+    /// The visible row at the screen line `y` of the list, taking scrolling into account.
     pub fn get_index_at_y(&self, y: usize) -> Option<usize> {
-        let visible_items = self.visible_items();
-        if y < visible_items.len() {
-            // Find the corresponding index in the original items list
-            let visible_item = &visible_items[y];
-            self.items.iter().position(|item| {
-                item.group == visible_item.group
-                    && item.host == visible_item.host
-                    && item.is_group == visible_item.is_group
-            })
-        } else {
-            None
-        }
+        let index = y + self.state.offset();
+        (index < self.visible_items().len()).then_some(index)
     }
 
     pub fn selected(&self) -> Option<&ServerItem> {
@@ -269,10 +1313,12 @@ impl ServerList {
             })
     }
 
+    /// Width of the widest host name including its indentation in the tree.
     pub fn max_host_len(&self) -> usize {
         self.items
             .iter()
-            .map(|item| item.host.width())
+            .filter(|item| !item.is_group)
+            .map(|item| item.indent().len() + item.host.width())
             .max()
             .unwrap_or(0)
     }
@@ -293,54 +1339,481 @@ impl ServerList {
     }
 }
 
+impl fmt::Display for ServerItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.host)
+    }
+}
+
 impl ServerItem {
-    pub fn new(
-        group: &str,
-        is_group: bool,
-        host: &str,
-        ip: &str,
-        username: &str,
-        port: u32,
-        private_key: &str,
-        password: Option<String>,
-    ) -> Self {
-        Self {
-            group: group.to_string(),
-            is_group,
-            host: host.to_string(),
-            ip: ip.to_string(),
-            username: username.to_string(),
-            port,
-            private_key: private_key.to_string(),
-            password,
+    /// Whether OpenSSH can reach this host by its name, which requires it to come from the config
+    /// and to be a single `Host` pattern.
+    pub fn has_ssh_alias(&self) -> bool {
+        self.source.is_some() && !self.host.contains(char::is_whitespace)
+    }
+
+    fn via(&self) -> String {
+        match (&self.proxy_jump, &self.proxy_command) {
+            (Some(proxy_jump), _) => format!(" via {}", proxy_jump),
+            (None, Some(_)) => " via ProxyCommand".to_string(),
+            (None, None) => String::new(),
         }
     }
 
-    pub fn to_string(&self) -> String {
-        format!("{}", self.host)
+    /// The address ssh connects to.
+    pub fn hostname(&self) -> &str {
+        self.ip.as_deref().unwrap_or(&self.host)
+    }
+
+    /// The user ssh logs in as, `None` if neither the config nor the environment tells.
+    pub fn effective_user(&self) -> Option<String> {
+        self.username.clone().or_else(local_user)
+    }
+
+    /// Nesting level in the tree, 0 for top-level groups and hosts without group.
+    pub fn depth(&self) -> usize {
+        let depth = if self.group == OTHER_GROUP {
+            0
+        } else {
+            self.group.matches(GROUP_SEPARATOR).count() + 1
+        };
+        if self.is_group {
+            depth.saturating_sub(1)
+        } else {
+            depth
+        }
+    }
+
+    fn indent(&self) -> String {
+        "  ".repeat(self.depth())
     }
 
     pub fn to_string_aligned(&self, max_host_len: usize, is_expanded: bool) -> String {
-        let host_width = self.host.width();
-        let padding = " ".repeat(max_host_len - host_width);
+        let indent = self.indent();
         if self.is_group {
             let arrow = if is_expanded { "▼" } else { "▶" };
-            format!("{} {}", arrow, self.group)
-        } else if OTHER_GROUP.eq(&self.group) {
-            format!("{}{} {}", self.host, padding, self.ip)
-        } else {
-            format!("  {}{} {}", self.host, padding, self.ip)
+            let name = self
+                .group
+                .rsplit(GROUP_SEPARATOR)
+                .next()
+                .unwrap_or_default();
+            return format!("{}{} {}", indent, arrow, name);
         }
+        let padding = " ".repeat(max_host_len.saturating_sub(indent.len() + self.host.width()));
+        format!(
+            "{}{}{} {}{}",
+            indent,
+            self.host,
+            padding,
+            self.hostname(),
+            self.via()
+        )
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::server::ServerList;
+    use crate::server::{
+        match_pattern, tokenize, ConfigWarning, Forward, ForwardKind, ServerItem, ServerList,
+    };
+    use crate::test_util::fixture;
+
+    fn find<'a>(list: &'a ServerList, host: &str) -> &'a ServerItem {
+        list.items.iter().find(|item| item.host == host).unwrap()
+    }
+
+    fn hosts(list: &ServerList) -> Vec<&str> {
+        list.items
+            .iter()
+            .filter(|item| !item.is_group)
+            .map(|item| item.host.as_str())
+            .collect()
+    }
 
     #[test]
     fn test() {
         let list = ServerList::from_ssh_config();
         println!("{}", serde_json::to_string(&list).unwrap());
     }
+
+    #[test]
+    fn test_include() {
+        let dir = fixture("include");
+        let list = ServerList::from_config_file(&dir.join("config"));
+        assert_eq!(
+            hosts(&list),
+            vec!["main", "dev1", "dev2", "prod1", "nested", "last"]
+        );
+
+        let source = |host: &str| {
+            list.items
+                .iter()
+                .find(|item| item.host == host)
+                .and_then(|item| item.source.clone())
+                .unwrap()
+        };
+        assert_eq!(source("main"), dir.join("config"));
+        assert_eq!(source("dev2"), dir.join("config.d/dev.conf"));
+        assert_eq!(source("prod1"), dir.join("config.d/prod.conf"));
+        assert_eq!(source("nested"), dir.join("nested.conf"));
+        assert_eq!(source("last"), dir.join("config"));
+    }
+
+    #[test]
+    fn test_match_pattern() {
+        assert!(match_pattern("web1.prod", "*.prod"));
+        assert!(match_pattern("WEB1.prod", "web?.PROD"));
+        assert!(match_pattern("anything", "*"));
+        assert!(match_pattern("a.b.prod", "*.*.prod"));
+        assert!(!match_pattern("web1.dev", "*.prod"));
+        assert!(!match_pattern("web10", "web?"));
+    }
+
+    #[test]
+    fn test_wildcard_blocks() {
+        let list = ServerList::from_config_file(&fixture("wildcard/config"));
+        assert_eq!(hosts(&list), vec!["web1.prod", "db1.prod", "bastion"]);
+
+        // `Host *.prod` comes first, so its User wins over the host's own block
+        let web = find(&list, "web1.prod");
+        assert_eq!(web.ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(web.username.as_deref(), Some("deploy"));
+        assert_eq!(web.private_key.as_deref(), Some("~/.ssh/keys/prod"));
+        assert_eq!(web.port, 2200);
+
+        // Excluded from the negated block
+        let db = find(&list, "db1.prod");
+        assert_eq!(db.username.as_deref(), Some("deploy"));
+        assert_eq!(db.port, 2222);
+
+        let bastion = find(&list, "bastion");
+        assert_eq!(bastion.username.as_deref(), Some("admin"));
+        assert_eq!(bastion.port, 22);
+    }
+
+    #[test]
+    fn test_match_blocks() {
+        let list = ServerList::from_config_file(&fixture("match/config"));
+        assert_eq!(hosts(&list), vec!["web1", "web2", "db1"]);
+
+        let web1 = find(&list, "web1");
+        assert_eq!(web1.ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(web1.username.as_deref(), Some("admin"));
+        assert_eq!(web1.port, 2201);
+        assert_eq!(
+            web1.identity_files,
+            vec!["~/.ssh/keys/admin", "~/.ssh/keys/default"]
+        );
+
+        // Directives after `Match` no longer end up in the preceding host block
+        let web2 = find(&list, "web2");
+        assert_eq!(web2.ip.as_deref(), Some("10.0.1.1"));
+        assert_eq!(web2.port, 2202);
+        assert_eq!(web2.private_key.as_deref(), Some("~/.ssh/keys/default"));
+
+        let db1 = find(&list, "db1");
+        assert_eq!(db1.ip.as_deref(), Some("10.0.2.1"));
+        assert_eq!(db1.port, 22);
+    }
+
+    #[test]
+    fn test_multiple_aliases() {
+        let list = ServerList::from_config_file(&fixture("multi/config"));
+        assert_eq!(
+            hosts(&list),
+            vec!["web1", "web2", "web3", "db1", "Media server", "server"]
+        );
+
+        for host in ["web1", "web2", "web3"] {
+            let web = find(&list, host);
+            assert_eq!(web.ip.as_deref(), Some("10.0.0.1"));
+            assert_eq!(web.username.as_deref(), Some("deploy"));
+            assert!(web.has_ssh_alias());
+        }
+        assert_eq!(find(&list, "db1").port, 2200);
+
+        let media = find(&list, "Media server");
+        assert_eq!(media.ip.as_deref(), Some("10.0.0.5"));
+        assert_eq!(media.password.as_deref(), Some("123456"));
+        assert!(!media.has_ssh_alias());
+
+        // Its words aren't hosts of their own
+        let server = find(&list, "server");
+        assert_eq!(server.ip, None);
+        assert_eq!(server.password, None);
+        assert!(list.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let cases: &[(&str, &[&str])] = &[
+            ("HostName 10.0.0.1", &["HostName", "10.0.0.1"]),
+            ("  hostname\t10.0.0.1  ", &["hostname", "10.0.0.1"]),
+            ("Port=2222", &["Port", "2222"]),
+            ("Port = 2222", &["Port", "2222"]),
+            ("Port =2222", &["Port", "2222"]),
+            (
+                "IdentityFile \"~/My Keys/id\"",
+                &["IdentityFile", "~/My Keys/id"],
+            ),
+            (
+                "IdentityFile '~/My Keys/id'",
+                &["IdentityFile", "~/My Keys/id"],
+            ),
+            (
+                "IdentityFile ~/My\\ Keys/id",
+                &["IdentityFile", "~/My Keys/id"],
+            ),
+            ("Host web1 web2 # staging", &["Host", "web1", "web2"]),
+            ("User deploy#1", &["User", "deploy#1"]),
+            ("# HostName 10.0.0.1", &[]),
+            ("", &[]),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(&tokenize(line), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_lexical_fixture() {
+        let list = ServerList::from_config_file(&fixture("lexical/config"));
+        assert_eq!(hosts(&list), vec!["lower", "equals", "quoted", "commented"]);
+
+        let lower = find(&list, "lower");
+        assert_eq!(lower.ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(lower.username.as_deref(), Some("root"));
+        assert_eq!(lower.port, 2201);
+
+        let equals = find(&list, "equals");
+        assert_eq!(equals.ip.as_deref(), Some("10.0.0.2"));
+        assert_eq!(equals.username.as_deref(), Some("deploy"));
+        assert_eq!(equals.port, 2202);
+
+        let quoted = find(&list, "quoted");
+        assert_eq!(quoted.private_key.as_deref(), Some("~/My Keys/id"));
+        assert_eq!(
+            quoted.identity_files,
+            vec!["~/My Keys/id", "~/.ssh/other key"]
+        );
+
+        let commented = find(&list, "commented");
+        assert_eq!(commented.ip.as_deref(), Some("10.0.0.4"));
+        assert_eq!(commented.port, 2204);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let config = fixture("diagnostics/config");
+        let list = ServerList::from_config_file(&config);
+        let warning = |line: usize, message: &str| ConfigWarning {
+            file: config.clone(),
+            line,
+            message: message.to_string(),
+        };
+        let missing = fixture("diagnostics/missing.conf");
+        assert_eq!(
+            list.warnings,
+            [
+                warning(7, "invalid port `abc`"),
+                warning(8, "unknown keyword `Colour`"),
+                warning(
+                    11,
+                    &format!(
+                        "duplicate host `web1`, first defined at {}:5",
+                        config.display()
+                    )
+                ),
+                warning(
+                    13,
+                    &format!(
+                        "cannot read `{}`: No such file or directory (os error 2)",
+                        missing.display()
+                    )
+                ),
+            ]
+        );
+        assert_eq!(find(&list, "web1").port, 22);
+
+        // Like OpenSSH, a host without HostName connects to its alias, which is no problem
+        let db1 = find(&list, "db1");
+        assert_eq!(db1.ip, None);
+        assert_eq!(db1.hostname(), "db1");
+        assert_eq!(db1.private_key, None);
+    }
+
+    #[test]
+    fn test_proxy() {
+        let list = ServerList::from_config_file(&fixture("proxy/config"));
+
+        let web1 = find(&list, "web1");
+        assert_eq!(
+            web1.proxy_jump.as_deref(),
+            Some("deploy@bastion:2222,10.0.5.5")
+        );
+        assert_eq!(
+            list.jump_chain(web1),
+            vec!["gateway (203.0.113.1)", "bastion (10.0.9.9)", "10.0.5.5"]
+        );
+
+        let legacy = find(&list, "legacy");
+        assert_eq!(
+            legacy.proxy_command.as_deref(),
+            Some("ssh -W \"%h:%p\" gateway")
+        );
+        assert_eq!(list.jump_chain(legacy), vec!["ProxyCommand"]);
+
+        // `none` set first disables the ProxyJump of `Host *`
+        let direct = find(&list, "direct");
+        assert_eq!(direct.proxy_jump, None);
+        assert!(list.jump_chain(direct).is_empty());
+
+        let loop1 = find(&list, "loop1");
+        assert_eq!(
+            list.jump_chain(loop1),
+            vec!["loop1 (loop)", "loop2 (10.0.7.2)"]
+        );
+    }
+
+    #[test]
+    fn test_forwards() {
+        let list = ServerList::from_config_file(&fixture("forward/config"));
+        let db = find(&list, "db");
+        let forward = |kind, listen: &str, target: Option<&str>| Forward {
+            kind,
+            listen: listen.to_string(),
+            target: target.map(str::to_string),
+        };
+        assert_eq!(
+            db.forwards,
+            vec![
+                forward(ForwardKind::Local, "5432", Some("localhost:5432")),
+                forward(
+                    ForwardKind::Local,
+                    "127.0.0.1:6380",
+                    Some("redis.internal:6379")
+                ),
+                forward(ForwardKind::Remote, "9000", Some("localhost:3000")),
+                forward(ForwardKind::Dynamic, "1080", None),
+            ]
+        );
+        assert_eq!(
+            db.forwards[1].cli_spec(),
+            "127.0.0.1:6380:redis.internal:6379"
+        );
+        assert_eq!(db.forwards[0].to_string(), "L 5432 → localhost:5432");
+        assert_eq!(db.forwards[1].local_port(), Some(6380));
+        assert_eq!(db.forwards[2].local_port(), None);
+        assert_eq!(db.forwards[3].local_port(), Some(1080));
+
+        let address = |listen: &str| {
+            let forward = forward(ForwardKind::Local, listen, Some("db:5432"));
+            forward.local_address().map(|address| address.to_string())
+        };
+        assert_eq!(address("5432").as_deref(), Some("127.0.0.1:5432"));
+        assert_eq!(address("*:5432").as_deref(), Some("127.0.0.1:5432"));
+        assert_eq!(address("10.0.0.5:5432").as_deref(), Some("10.0.0.5:5432"));
+        assert_eq!(address("[::1]:5432").as_deref(), Some("[::1]:5432"));
+        assert_eq!(address("[::]:5432").as_deref(), Some("[::1]:5432"));
+        assert_eq!(address("fe80::1/5432").as_deref(), Some("[fe80::1]:5432"));
+        assert_eq!(address("/tmp/db.sock"), None);
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let mut list = ServerList::from_config_file(&fixture("nested/config"));
+        let rows = |list: &ServerList| -> Vec<String> {
+            let max_host_len = list.max_host_len();
+            list.visible_items()
+                .iter()
+                .map(|item| {
+                    let expanded = list.is_group_expanded(&item.group);
+                    item.to_string_aligned(max_host_len, expanded)
+                })
+                .collect()
+        };
+        assert_eq!(
+            rows(&list),
+            vec![
+                "laptop         192.168.1.10",
+                "▼ Prod",
+                "  prod-bastion 10.0.0.1",
+                "  ▼ EU",
+                "    ▼ Web",
+                "      web-eu1  10.1.0.1",
+                "  ▼ US",
+                "    web-us1    10.3.0.1",
+                "▼ Dev",
+                "  dev1         10.2.0.1",
+            ]
+        );
+        assert_eq!(find(&list, "web-us1").group, "Prod/US");
+        // Counted in every group they are nested in
+        assert_eq!(list.subtree_hosts("Prod").len(), 3);
+        assert_eq!(list.subtree_hosts("Prod/EU").len(), 1);
+        // `Prod/EU` only exists as the parent of `Prod/EU/Web`
+        let eu = list.items.iter().find(|i| i.group == "Prod/EU").unwrap();
+        assert!(eu.is_group && eu.source.is_none());
+
+        // Collapsing a group hides its whole subtree and collapses the groups nested in it
+        list.state.select(Some(1));
+        list.toggle_group();
+        assert_eq!(
+            rows(&list),
+            vec![
+                "laptop         192.168.1.10",
+                "▶ Prod",
+                "▼ Dev",
+                "  dev1         10.2.0.1",
+            ]
+        );
+        assert!(!list.is_group_expanded("Prod/EU/Web"));
+        list.toggle_group();
+        assert_eq!(rows(&list).len(), 10);
+
+        assert_eq!(
+            list.sibling_group("Prod/EU", true).as_deref(),
+            Some("Prod/US")
+        );
+        assert_eq!(list.sibling_group("Prod", true).as_deref(), Some("Dev"));
+        assert_eq!(list.sibling_group("Prod", false), None);
+    }
+
+    #[test]
+    fn test_reserved_group() {
+        let config = fixture("reserved/config");
+        let list = ServerList::from_config_file(&config);
+        let max_host_len = list.max_host_len();
+        let rows: Vec<String> = list
+            .visible_items()
+            .iter()
+            .map(|item| item.to_string_aligned(max_host_len, true))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                "misc   10.0.0.2",
+                "lab1   10.0.0.3",
+                "▼ Dev",
+                "  dev1 10.0.0.1",
+            ]
+        );
+        let messages: Vec<(usize, &str)> = list
+            .warnings
+            .iter()
+            .map(|warning| (warning.line, warning.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (
+                    5,
+                    "group name `other` is reserved, its hosts are listed without group"
+                ),
+                (
+                    9,
+                    "group name `other/Lab` is reserved, its hosts are listed without group"
+                ),
+            ]
+        );
+    }
 }
@@ -1,43 +1,139 @@
+use crate::askpass::Askpass;
+use crate::secret::{password, SecretProviders};
 use crate::server::ServerItem;
+use std::io;
+use std::path::Path;
 use std::process::Command;
 
-pub fn ssh_login(server: &ServerItem) {
-    if let Some(password) = &server.password {
-        // Use sshpass for password-based login
-        let ssh_cmd = format!(
-            "sshpass -p '{}' ssh {}@{} -p {}",
-            "*".repeat(password.len()), server.username, server.ip, server.port
+pub fn ssh_login(server: &ServerItem, config: &Path) {
+    let mut server = server.clone();
+    match password(&server, &SecretProviders::default()) {
+        Ok(password) => server.password = password,
+        // ssh asks for the password itself then
+        Err(e) => eprintln!("Cannot fetch the password of {}: {}", server.host, e),
+    }
+    let args = ssh_args(&server, config);
+    println!("Executing: ssh {}", args.join(" "));
+
+    let (mut command, _askpass) =
+        ssh_command(&server, &args, true).expect("Failed to start SSH session");
+    command
+        .spawn()
+        .expect("Failed to start SSH session")
+        .wait()
+        .expect("SSH process failed");
+}
+
+/// `ssh` with `args`. For password-based login lazyssh answers the password prompt itself as
+/// long as the returned `Askpass` is kept, `interactive` tells whether other prompts can be
+/// asked on the terminal.
+pub fn ssh_command(
+    server: &ServerItem,
+    args: &[String],
+    interactive: bool,
+) -> io::Result<(Command, Option<Askpass>)> {
+    let mut command = Command::new("ssh");
+    command.args(args);
+    let askpass = match &server.password {
+        Some(password) => {
+            let askpass = Askpass::serve(password, "password")?;
+            askpass.configure(&mut command, interactive)?;
+            Some(askpass)
+        }
+        None => None,
+    };
+    Ok((command, askpass))
+}
+
+/// Hosts from the config are reached by their alias so OpenSSH applies the whole host block
+/// (ProxyJump, ControlMaster, SetEnv, ...), the connection arguments are only rebuilt for hosts
+/// OpenSSH doesn't know about.
+pub fn ssh_args(server: &ServerItem, config: &Path) -> Vec<String> {
+    if server.has_ssh_alias() {
+        return vec![
+            "-F".to_string(),
+            config.display().to_string(),
+            server.host.clone(),
+        ];
+    }
+
+    let destination = match &server.username {
+        Some(user) => format!("{}@{}", user, server.hostname()),
+        None => server.hostname().to_string(),
+    };
+    let mut args = vec![destination, "-p".to_string(), server.port.to_string()];
+    if let Some(proxy_jump) = &server.proxy_jump {
+        args.push("-J".to_string());
+        args.push(proxy_jump.clone());
+    } else if let Some(proxy_command) = &server.proxy_command {
+        args.push("-o".to_string());
+        args.push(format!("ProxyCommand={}", proxy_command));
+    }
+    if let (None, Some(private_key)) = (&server.password, &server.private_key) {
+        args.push("-i".to_string());
+        args.push(private_key.clone());
+    }
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::ServerItem;
+    use crate::ssh_login::ssh_args;
+    use std::path::Path;
+
+    fn server(host: &str, source: Option<&str>) -> ServerItem {
+        ServerItem {
+            group: "other".to_string(),
+            is_group: false,
+            host: host.to_string(),
+            ip: Some("10.0.0.1".to_string()),
+            username: Some("root".to_string()),
+            port: 2222,
+            private_key: Some("~/.ssh/keys/web".to_string()),
+            identity_files: vec!["~/.ssh/keys/web".to_string()],
+            forwards: Vec::new(),
+            proxy_jump: None,
+            proxy_command: None,
+            password: None,
+            password_from: None,
+            source: source.map(Into::into),
+            line: 0,
+        }
+    }
+
+    #[test]
+    fn test_ssh_args() {
+        let config = Path::new("/home/me/.ssh/config");
+
+        let from_config = server("web1", Some("/home/me/.ssh/config.d/web"));
+        assert_eq!(
+            ssh_args(&from_config, config),
+            vec!["-F", "/home/me/.ssh/config", "web1"]
         );
-        println!("Executing: {}", ssh_cmd);
-
-        Command::new("sshpass")
-            .arg("-p")
-            .arg(password)
-            .arg("ssh")
-            .arg(format!("{}@{}", server.username, server.ip))
-            .arg("-p")
-            .arg(&server.port.to_string())
-            .spawn()
-            .expect("Failed to start SSH session with password")
-            .wait()
-            .expect("SSH process failed");
-    } else {
-        // Use key-based login
-        let ssh_cmd = format!(
-            "ssh {}@{} -p {} -i {}",
-            server.username, server.ip, server.port, server.private_key
+
+        let synthesized = server("web1", None);
+        assert_eq!(
+            ssh_args(&synthesized, config),
+            vec!["root@10.0.0.1", "-p", "2222", "-i", "~/.ssh/keys/web"]
+        );
+
+        // OpenSSH would read a name with spaces as several patterns, none matching the whole name
+        let spaced = server("Media server", Some("/home/me/.ssh/config"));
+        assert_eq!(ssh_args(&spaced, config)[0], "root@10.0.0.1");
+
+        // Nothing is made up for missing settings
+        let mut bare = server("web1", None);
+        bare.ip = None;
+        bare.username = None;
+        bare.private_key = None;
+        assert_eq!(ssh_args(&bare, config), vec!["web1", "-p", "2222"]);
+
+        let mut behind_bastion = server("web1", None);
+        behind_bastion.proxy_jump = Some("bastion".to_string());
+        assert_eq!(
+            ssh_args(&behind_bastion, config)[3..5],
+            ["-J".to_string(), "bastion".to_string()]
         );
-        println!("Executing: {}", ssh_cmd);
-
-        Command::new("ssh")
-            .arg(format!("{}@{}", server.username, server.ip))
-            .arg("-p")
-            .arg(&server.port.to_string())
-            .arg("-i")
-            .arg(&server.private_key)
-            .spawn()
-            .expect("Failed to start SSH session with key")
-            .wait()
-            .expect("SSH process failed");
     }
 }
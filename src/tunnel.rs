@@ -0,0 +1,312 @@
+use crate::askpass::Askpass;
+use crate::secret::{password, SecretProviders};
+use crate::server::ServerItem;
+use crate::ssh_login::{ssh_args, ssh_command};
+use ratatui::widgets::TableState;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
+
+// Wait before restarting a tunnel whose ssh exited, so a host that's down isn't hammered
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+const PORT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const PORT_CHECK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// An `ssh -N` process running in the background to keep a host's forwards open. It is stopped
+/// when dropped, so tunnels live as long as lazyssh does.
+#[derive(Debug)]
+pub struct Tunnel {
+    pub server: ServerItem,
+    config: PathBuf,
+    child: Option<Child>,
+    /// Answers ssh's password prompt for hosts with `#: Password`.
+    askpass: Option<Askpass>,
+    /// When the current ssh process was started.
+    pub started: Instant,
+    /// When ssh exited, it is restarted `RESTART_DELAY` later.
+    pub exited_at: Option<Instant>,
+    pub restarts: u32,
+    /// Local addresses of the forwards and whether they accept connections.
+    pub ports: Vec<(SocketAddr, bool)>,
+    last_port_check: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct TunnelManager {
+    pub tunnels: Vec<Tunnel>,
+    pub state: TableState,
+}
+
+impl Tunnel {
+    pub fn start(server: &ServerItem, config: &Path) -> io::Result<Self> {
+        let mut tunnel = Self::new(server, config);
+        tunnel.spawn()?;
+        Ok(tunnel)
+    }
+
+    fn new(server: &ServerItem, config: &Path) -> Self {
+        Self {
+            server: server.clone(),
+            config: config.to_path_buf(),
+            child: None,
+            askpass: None,
+            started: Instant::now(),
+            exited_at: None,
+            restarts: 0,
+            ports: server
+                .forwards
+                .iter()
+                .filter_map(|forward| forward.local_address())
+                .map(|port| (port, false))
+                .collect(),
+            last_port_check: None,
+        }
+    }
+
+    fn spawn(&mut self) -> io::Result<()> {
+        // Fetched again on every restart, the password may have changed in the meantime
+        let mut server = self.server.clone();
+        server.password = password(&server, &SecretProviders::default())?;
+        let args = tunnel_args(&server, &self.config);
+        let (mut command, askpass) = ssh_command(&server, &args, false)?;
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        self.child = Some(child);
+        self.askpass = askpass;
+        self.started = Instant::now();
+        self.exited_at = None;
+        Ok(())
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.child.as_ref().map(Child::id)
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        match &mut self.child {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.askpass = None;
+    }
+
+    pub fn restart(&mut self) -> io::Result<()> {
+        self.stop();
+        self.restarts += 1;
+        self.spawn()
+    }
+
+    /// Notices ssh exiting, restarts it once `RESTART_DELAY` passed and refreshes the port
+    /// status. Returns a message when something changed.
+    fn poll(&mut self) -> Option<String> {
+        let mut message = None;
+        if self.exited_at.is_none() && !self.is_running() {
+            self.stop();
+            self.exited_at = Some(Instant::now());
+            self.ports.iter_mut().for_each(|(_, open)| *open = false);
+            message = Some(format!("Tunnel to {} exited", self.server.host));
+        }
+        if let Some(exited_at) = self.exited_at {
+            if exited_at.elapsed() >= RESTART_DELAY {
+                message = Some(match self.restart() {
+                    Ok(()) => format!("Tunnel to {} restarted", self.server.host),
+                    Err(e) => {
+                        self.exited_at = Some(Instant::now());
+                        format!("Failed to restart tunnel to {}: {}", self.server.host, e)
+                    }
+                });
+            }
+            return message;
+        }
+
+        if self
+            .last_port_check
+            .is_none_or(|checked| checked.elapsed() >= PORT_CHECK_INTERVAL)
+        {
+            self.check_ports();
+        }
+        message
+    }
+
+    fn check_ports(&mut self) {
+        for (address, open) in &mut self.ports {
+            *open = TcpStream::connect_timeout(address, PORT_CHECK_TIMEOUT).is_ok();
+        }
+        self.last_port_check = Some(Instant::now());
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl TunnelManager {
+    pub fn position(&self, host: &str) -> Option<usize> {
+        self.tunnels.iter().position(|t| t.server.host == host)
+    }
+
+    pub fn start(&mut self, server: &ServerItem, config: &Path) -> io::Result<()> {
+        self.tunnels.push(Tunnel::start(server, config)?);
+        if self.state.selected().is_none() {
+            self.state.select(Some(0));
+        }
+        Ok(())
+    }
+
+    pub fn stop(&mut self, index: usize) -> Option<Tunnel> {
+        if index >= self.tunnels.len() {
+            return None;
+        }
+        let mut tunnel = self.tunnels.remove(index);
+        tunnel.stop();
+        if self.tunnels.is_empty() {
+            self.state.select(None);
+        } else if index >= self.tunnels.len() {
+            self.state.select(Some(self.tunnels.len() - 1));
+        }
+        Some(tunnel)
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.state.selected().filter(|&i| i < self.tunnels.len())
+    }
+
+    /// Polls every tunnel, returning the messages of those whose state changed.
+    pub fn poll(&mut self) -> Vec<String> {
+        self.tunnels.iter_mut().filter_map(Tunnel::poll).collect()
+    }
+}
+
+fn tunnel_args(server: &ServerItem, config: &Path) -> Vec<String> {
+    let mut args = vec![
+        "-N".to_string(),
+        "-o".to_string(),
+        "ExitOnForwardFailure=yes".to_string(),
+    ];
+    // There is nobody to answer a passphrase prompt, lazyssh answers the password one itself
+    if server.password.is_none() {
+        args.push("-o".to_string());
+        args.push("BatchMode=yes".to_string());
+    }
+    // OpenSSH reads the forwards from the host block when logging in by alias
+    if !server.has_ssh_alias() {
+        for forward in &server.forwards {
+            args.push(forward.flag().to_string());
+            args.push(forward.cli_spec());
+        }
+    }
+    args.extend(ssh_args(server, config));
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::{Forward, ForwardKind, ServerItem};
+    use crate::tunnel::{tunnel_args, Tunnel};
+    use std::net::TcpListener;
+    use std::path::Path;
+
+    #[test]
+    fn test_tunnel_args() {
+        let mut server = ServerItem {
+            group: "other".to_string(),
+            is_group: false,
+            host: "db".to_string(),
+            ip: Some("10.0.0.1".to_string()),
+            username: None,
+            port: 22,
+            private_key: None,
+            identity_files: Vec::new(),
+            forwards: vec![Forward {
+                kind: ForwardKind::Local,
+                listen: "5432".to_string(),
+                target: Some("localhost:5432".to_string()),
+            }],
+            proxy_jump: None,
+            proxy_command: None,
+            password: None,
+            password_from: None,
+            source: Some("/home/me/.ssh/config".into()),
+            line: 0,
+        };
+        let config = Path::new("/home/me/.ssh/config");
+        assert_eq!(
+            tunnel_args(&server, config),
+            vec![
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-o",
+                "BatchMode=yes",
+                "-F",
+                "/home/me/.ssh/config",
+                "db"
+            ]
+        );
+
+        server.source = None;
+        server.password = Some("secret".to_string());
+        assert_eq!(
+            tunnel_args(&server, config),
+            vec![
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-L",
+                "5432:localhost:5432",
+                "10.0.0.1",
+                "-p",
+                "22"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_port_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let port = address.port();
+        let server = ServerItem {
+            group: "other".to_string(),
+            is_group: false,
+            host: "local".to_string(),
+            ip: Some("127.0.0.1".to_string()),
+            username: None,
+            port: 22,
+            private_key: None,
+            identity_files: Vec::new(),
+            forwards: vec![Forward {
+                kind: ForwardKind::Local,
+                listen: port.to_string(),
+                target: Some("localhost:80".to_string()),
+            }],
+            proxy_jump: None,
+            proxy_command: None,
+            password: None,
+            password_from: None,
+            source: None,
+            line: 0,
+        };
+        let mut tunnel = Tunnel::new(&server, Path::new("/dev/null"));
+        tunnel.check_ports();
+        assert_eq!(tunnel.ports, vec![(address, true)]);
+
+        drop(listener);
+        tunnel.check_ports();
+        assert_eq!(tunnel.ports, vec![(address, false)]);
+    }
+}
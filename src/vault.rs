@@ -0,0 +1,247 @@
+use crate::config_file::write_atomic;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, Zeroizing};
+
+const MAGIC: &[u8] = b"LZSVAULT";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+// Magic, version, the three Argon2 costs, salt and nonce, authenticated along with the secrets
+const HEADER_LEN: usize = MAGIC.len() + 1 + 3 * 4 + SALT_LEN + NONCE_LEN;
+// Argon2id with 64 MiB of memory and 3 passes, taking about a second to unlock
+const MEMORY_COST: u32 = 64 * 1024;
+const TIME_COST: u32 = 3;
+const PARALLELISM: u32 = 1;
+
+/// Host secrets keyed by alias, stored encrypted with a key derived from a master passphrase.
+/// The file is authenticated, so a wrong passphrase and a tampered file are both refused.
+pub struct Vault {
+    path: PathBuf,
+    params: Params,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; KEY_LEN]>,
+    secrets: BTreeMap<String, String>,
+}
+
+/// Where the vault is kept, next to the backups.
+pub fn vault_path() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join("lazyssh").join("vault"))
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: Params,
+) -> io::Result<Zeroizing<[u8; KEY_LEN]>> {
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(key)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Vault {
+    /// An empty vault locked with `passphrase`, written on the first `save`.
+    pub fn create(path: &Path, passphrase: &str) -> io::Result<Self> {
+        let params = Params::new(MEMORY_COST, TIME_COST, PARALLELISM, Some(KEY_LEN))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Self::create_with(path, passphrase, params)
+    }
+
+    /// An empty vault with a cheap key derivation for the tests, the default one is slow on
+    /// purpose.
+    #[cfg(test)]
+    pub fn create_fast(path: &Path, passphrase: &str) -> io::Result<Self> {
+        let params = Params::new(64, 1, 1, Some(KEY_LEN)).unwrap();
+        Self::create_with(path, passphrase, params)
+    }
+
+    fn create_with(path: &Path, passphrase: &str, params: Params) -> io::Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Ok(Self {
+            path: path.to_path_buf(),
+            key: derive_key(passphrase, &salt, params.clone())?,
+            params,
+            salt,
+            secrets: BTreeMap::new(),
+        })
+    }
+
+    /// Decrypts the vault at `path`, failing if the passphrase is wrong or the file was altered.
+    pub fn open(path: &Path, passphrase: &str) -> io::Result<Self> {
+        let content = fs::read(path)?;
+        if content.len() < HEADER_LEN || !content.starts_with(MAGIC) {
+            return Err(invalid("Not a lazyssh vault"));
+        }
+        let (header, ciphertext) = content.split_at(HEADER_LEN);
+        if header[MAGIC.len()] != VERSION {
+            return Err(invalid("Unsupported vault version"));
+        }
+        let cost = |i: usize| {
+            let start = MAGIC.len() + 1 + i * 4;
+            u32::from_le_bytes(header[start..start + 4].try_into().unwrap())
+        };
+        let params = Params::new(cost(0), cost(1), cost(2), Some(KEY_LEN))
+            .map_err(|_| invalid("Invalid vault settings"))?;
+        let salt_start = MAGIC.len() + 1 + 3 * 4;
+        let salt: [u8; SALT_LEN] = header[salt_start..salt_start + SALT_LEN]
+            .try_into()
+            .unwrap();
+        let nonce = Nonce::from_slice(&header[salt_start + SALT_LEN..]);
+
+        let key = derive_key(passphrase, &salt, params.clone())?;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| invalid("Wrong passphrase or damaged vault"))?;
+        let secrets =
+            serde_json::from_slice(&plaintext).map_err(|_| invalid("Damaged vault content"))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            params,
+            salt,
+            key,
+            secrets,
+        })
+    }
+
+    /// Encrypts the secrets with a fresh nonce and writes the vault, only readable by the user.
+    pub fn save(&self) -> io::Result<()> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut content = MAGIC.to_vec();
+        content.push(VERSION);
+        for cost in [
+            self.params.m_cost(),
+            self.params.t_cost(),
+            self.params.p_cost(),
+        ] {
+            content.extend(cost.to_le_bytes());
+        }
+        content.extend(self.salt);
+        content.extend(nonce);
+
+        let plaintext = Zeroizing::new(serde_json::to_vec(&self.secrets)?);
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(self.key.as_ref()))
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: &content,
+                },
+            )
+            .map_err(|_| io::Error::other("Cannot encrypt the vault"))?;
+        content.extend(ciphertext);
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // The new content gets the permissions of the file it replaces, kept private even if
+        // they were widened
+        #[cfg(unix)]
+        if self.path.exists() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))?;
+        }
+        write_atomic(&self.path, content)
+    }
+
+    pub fn get(&self, alias: &str) -> Option<&str> {
+        self.secrets.get(alias).map(String::as_str)
+    }
+
+    pub fn set(&mut self, alias: &str, secret: &str) {
+        if let Some(mut old) = self.secrets.insert(alias.to_string(), secret.to_string()) {
+            old.zeroize();
+        }
+    }
+
+    pub fn remove(&mut self, alias: &str) {
+        if let Some(mut old) = self.secrets.remove(alias) {
+            old.zeroize();
+        }
+    }
+}
+
+impl Drop for Vault {
+    fn drop(&mut self) {
+        self.secrets.values_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+// Keeps the secrets out of debug output
+impl fmt::Debug for Vault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vault")
+            .field("path", &self.path)
+            .field("hosts", &self.secrets.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::temp_dir;
+    use crate::vault::{Vault, KEY_LEN, MAGIC};
+    use argon2::Params;
+    use std::fs;
+
+    #[test]
+    fn test_vault() {
+        let path = temp_dir("vault").join("vault");
+        // Cheap key derivation, the default one is slow on purpose
+        let params = Params::new(64, 1, 1, Some(KEY_LEN)).unwrap();
+
+        let mut vault = Vault::create_with(&path, "correct horse", params).unwrap();
+        vault.set("web1", "s3cret");
+        vault.set("db1", "hunter2");
+        vault.save().unwrap();
+
+        let content = fs::read(&path).unwrap();
+        assert!(content.starts_with(MAGIC));
+        assert!(!String::from_utf8_lossy(&content).contains("s3cret"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut vault = Vault::open(&path, "correct horse").unwrap();
+        assert_eq!(vault.get("web1"), Some("s3cret"));
+        assert_eq!(vault.get("db1"), Some("hunter2"));
+        assert!(!format!("{:?}", vault).contains("s3cret"));
+
+        vault.remove("web1");
+        vault.save().unwrap();
+        let vault = Vault::open(&path, "correct horse").unwrap();
+        assert_eq!(vault.get("web1"), None);
+
+        assert!(Vault::open(&path, "wrong horse").is_err());
+
+        // Any altered byte, header included, is detected
+        let mut content = fs::read(&path).unwrap();
+        content[MAGIC.len() + 20] ^= 1;
+        fs::write(&path, &content).unwrap();
+        assert!(Vault::open(&path, "correct horse").is_err());
+    }
+}